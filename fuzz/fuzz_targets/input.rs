@@ -0,0 +1,78 @@
+//! Runs an arbitrary ROM while pressing and releasing arbitrary keys, with arbitrary quirks, and
+//! restores arbitrary saved states
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use chip8_rs::emulator::{Chip8, Quirks, SaveState};
+use libfuzzer_sys::fuzz_target;
+
+/// Something done to the emulator
+#[derive(Debug, Arbitrary)]
+enum Event {
+    /// Presses a key. Includes indices outside `0x0..=0xF`.
+    PressKey(u8),
+    /// Releases a key. Includes indices outside `0x0..=0xF`.
+    ReleaseKey(u8),
+    ReleaseAllKeys,
+    /// Runs this many CPU cycles
+    Run(u8),
+    TickTimers,
+    /// Runs a whole frame, skipping idle cycles
+    RunFrame,
+    /// Resets the emulator and loads the ROM again
+    Reset,
+    /// Keeps a copy of the machine to restore later
+    SaveMachine,
+    /// Restores a copy of the machine kept earlier, wrapping around the copies kept so far
+    SetMachine(u8),
+    /// Loads a state from JSON, which may well be invalid
+    LoadState(Vec<u8>),
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    quirks: u8,
+    decode_cache: bool,
+    rom: Vec<u8>,
+    events: Vec<Event>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut emulator = Chip8::new().override_quirks(Quirks::from_bits_truncate(input.quirks));
+    if input.decode_cache {
+        emulator = emulator.with_decode_cache();
+    }
+    emulator.load(&input.rom);
+    let mut saved = Vec::new();
+
+    for event in input.events {
+        match event {
+            Event::PressKey(key) => emulator.press_key(key as usize),
+            Event::ReleaseKey(key) => emulator.release_key(key as usize),
+            Event::ReleaseAllKeys => emulator.release_all_keys(),
+            Event::Run(cycles) => {
+                for _ in 0..cycles {
+                    emulator.tick_cpu();
+                }
+            }
+            Event::TickTimers => emulator.tick_timers(),
+            Event::RunFrame => emulator.run_frame(),
+            Event::Reset => {
+                emulator.reset();
+                emulator.load(&input.rom);
+            }
+            Event::SaveMachine => saved.push(emulator.machine().clone()),
+            Event::SetMachine(index) => {
+                if !saved.is_empty() {
+                    emulator.set_machine(saved[index as usize % saved.len()].clone());
+                }
+            }
+            Event::LoadState(json) => {
+                if let Ok(state) = serde_json::from_slice::<SaveState>(&json) {
+                    let _ = emulator.load_state(&state);
+                }
+            }
+        }
+    }
+});
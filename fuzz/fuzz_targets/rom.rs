@@ -0,0 +1,29 @@
+//! Runs arbitrary bytes as a ROM under every combination of quirks, with and without the decode
+//! cache
+
+#![no_main]
+
+use chip8_rs::emulator::{Chip8, Quirks, cycles_in_frame};
+use libfuzzer_sys::fuzz_target;
+
+/// The number of 60Hz frames each ROM is run for under each combination of quirks and cache
+const FRAMES: u64 = 30;
+
+fuzz_target!(|rom: &[u8]| {
+    for decode_cache in [false, true] {
+        for bits in 0..=Quirks::all().bits() {
+            let mut emulator = Chip8::new().override_quirks(Quirks::from_bits_truncate(bits));
+            if decode_cache {
+                emulator = emulator.with_decode_cache();
+            }
+            emulator.load(rom);
+
+            for frame in 0..FRAMES {
+                for _ in 0..cycles_in_frame(frame) {
+                    emulator.tick_cpu();
+                }
+                emulator.tick_timers();
+            }
+        }
+    }
+});
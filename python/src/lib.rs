@@ -0,0 +1,347 @@
+//! Python bindings for the emulator core, built with [maturin](https://www.maturin.rs/).
+//!
+//! ```python
+//! import chip8
+//! import numpy as np
+//!
+//! emulator = chip8.Chip8(quirks="schip")
+//! emulator.load_rom_file("pong.ch8")
+//! emulator.press_key(0x5)
+//! emulator.run_frames(60)
+//! pixels = np.asarray(emulator.framebuffer())  # 32x64 array of 0s and 1s
+//! ```
+
+#![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
+
+#[pyo3::pymodule]
+mod chip8 {
+    use std::{
+        path::PathBuf,
+        sync::{Mutex, MutexGuard},
+    };
+
+    use chip8_rs::{
+        emulator::{self, Quirks, SaveState},
+        machine::Machine,
+    };
+    use pyo3::{
+        exceptions::{PyRuntimeError, PyValueError},
+        prelude::*,
+        types::{PyBytes, PyDict},
+    };
+
+    /// Adds a constant for each quirk, and a dict of the quirks of each platform profile
+    #[pymodule_init]
+    fn init(m: &Bound<'_, PyModule>) -> PyResult<()> {
+        for (name, quirk) in Quirks::all().iter_names() {
+            m.add(name, quirk.bits())?;
+        }
+
+        let profiles = PyDict::new(m.py());
+        for (name, quirks) in Quirks::PROFILES {
+            profiles.set_item(name, quirks.bits())?;
+        }
+        m.add("PROFILES", profiles)
+    }
+
+    /// Quirks given either as a profile name such as `"schip"`, or as the bits of the quirk
+    /// constants ORed together
+    #[derive(FromPyObject)]
+    enum QuirksArg {
+        Profile(String),
+        Bits(u8),
+    }
+
+    impl TryFrom<QuirksArg> for Quirks {
+        type Error = PyErr;
+
+        fn try_from(quirks: QuirksArg) -> PyResult<Self> {
+            match quirks {
+                QuirksArg::Profile(name) => Quirks::PROFILES
+                    .iter()
+                    .find(|(profile, _)| *profile == name)
+                    .map(|&(_, quirks)| quirks)
+                    .ok_or_else(|| {
+                        let names: Vec<_> =
+                            Quirks::PROFILES.iter().map(|(name, _)| *name).collect();
+                        PyValueError::new_err(format!(
+                            "Unknown profile `{}`, expected one of: {}",
+                            name,
+                            names.join(", ")
+                        ))
+                    }),
+                QuirksArg::Bits(bits) => Quirks::from_bits(bits)
+                    .ok_or_else(|| PyValueError::new_err(format!("Invalid quirks: {:#x}", bits))),
+            }
+        }
+    }
+
+    /// A CHIP-8 emulator.
+    ///
+    /// Runs as fast as it's stepped, without a window or sound. `quirks` is a profile name
+    /// from `PROFILES`, or the quirk constants ORed together, and defaults to `"chip8"`.
+    #[pyclass(frozen)]
+    struct Chip8 {
+        emulator: Mutex<emulator::Chip8>,
+    }
+
+    impl Chip8 {
+        fn lock(&self) -> PyResult<MutexGuard<'_, emulator::Chip8>> {
+            self.emulator
+                .lock()
+                .map_err(|_| PyRuntimeError::new_err("The emulator panicked"))
+        }
+    }
+
+    /// Checks a key index is in the range `0x0..=0xF`
+    fn check_key(key: usize) -> PyResult<()> {
+        if key > 0xF {
+            return Err(PyValueError::new_err(format!(
+                "Key {} is out of range, expected 0 to 15",
+                key
+            )));
+        }
+        Ok(())
+    }
+
+    #[pymethods]
+    impl Chip8 {
+        #[new]
+        #[pyo3(signature = (quirks = None))]
+        fn new(quirks: Option<QuirksArg>) -> PyResult<Self> {
+            let quirks = quirks.map_or(Ok(Quirks::default()), Quirks::try_from)?;
+            Ok(Self {
+                emulator: Mutex::new(
+                    emulator::Chip8::new()
+                        .override_quirks(quirks)
+                        .with_decode_cache(),
+                ),
+            })
+        }
+
+        /// Resets the emulator and loads a program
+        fn load_rom(&self, data: Vec<u8>) -> PyResult<()> {
+            let mut emulator = self.lock()?;
+            emulator.reset();
+            emulator.load(&data);
+            Ok(())
+        }
+
+        /// Resets the emulator and loads a program from a file
+        fn load_rom_file(&self, path: PathBuf) -> PyResult<()> {
+            self.load_rom(std::fs::read(path)?)
+        }
+
+        /// Resets the machine to its power-on state, discarding any loaded program
+        fn reset(&self) -> PyResult<()> {
+            self.lock()?.reset();
+            Ok(())
+        }
+
+        /// Runs a number of CPU cycles without ticking the timers
+        #[pyo3(signature = (cycles = 1))]
+        fn step(&self, py: Python<'_>, cycles: u64) -> PyResult<()> {
+            py.detach(|| {
+                let mut emulator = self.lock()?;
+                for _ in 0..cycles {
+                    emulator.tick_cpu();
+                }
+                Ok(())
+            })
+        }
+
+        /// Runs a number of whole 60Hz frames, each running the CPU then ticking the timers
+        #[pyo3(signature = (frames = 1))]
+        fn run_frames(&self, py: Python<'_>, frames: u64) -> PyResult<()> {
+            py.detach(|| {
+                let mut emulator = self.lock()?;
+                for _ in 0..frames {
+                    emulator.run_frame();
+                }
+                Ok(())
+            })
+        }
+
+        /// Presses a key, `0` to `15`
+        fn press_key(&self, key: usize) -> PyResult<()> {
+            check_key(key)?;
+            self.lock()?.press_key(key);
+            Ok(())
+        }
+
+        /// Releases a key, `0` to `15`. This completes a pending `Fx0A` key wait.
+        fn release_key(&self, key: usize) -> PyResult<()> {
+            check_key(key)?;
+            self.lock()?.release_key(key);
+            Ok(())
+        }
+
+        /// Releases every key without completing a pending `Fx0A` key wait
+        fn release_all_keys(&self) -> PyResult<()> {
+            self.lock()?.release_all_keys();
+            Ok(())
+        }
+
+        /// The 16 VX registers
+        #[getter]
+        fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+            Ok(PyBytes::new(py, self.lock()?.registers()))
+        }
+
+        /// The index (I) register
+        #[getter]
+        fn index_register(&self) -> PyResult<u16> {
+            Ok(self.lock()?.index_register())
+        }
+
+        /// The program counter
+        #[getter]
+        fn program_counter(&self) -> PyResult<u16> {
+            Ok(self.lock()?.program_counter())
+        }
+
+        /// Return addresses on the stack, from the bottom up
+        #[getter]
+        fn stack(&self) -> PyResult<Vec<u16>> {
+            Ok(self.lock()?.machine().stack().to_vec())
+        }
+
+        /// The delay timer
+        #[getter]
+        fn delay_timer(&self) -> PyResult<u8> {
+            Ok(self.lock()?.delay_timer())
+        }
+
+        /// The sound timer
+        #[getter]
+        fn sound_timer(&self) -> PyResult<u8> {
+            Ok(self.lock()?.sound_timer())
+        }
+
+        /// The number of frames run since the last reset
+        #[getter]
+        fn frame(&self) -> PyResult<u64> {
+            Ok(self.lock()?.machine().frame())
+        }
+
+        /// The quirks in use, as the quirk constants ORed together. Can be set to a profile
+        /// name or quirk constants, keeping the machine's state.
+        #[getter]
+        fn quirks(&self) -> PyResult<u8> {
+            Ok(self.lock()?.quirks().bits())
+        }
+
+        #[setter]
+        fn set_quirks(&self, quirks: QuirksArg) -> PyResult<()> {
+            let quirks = Quirks::try_from(quirks)?;
+            let mut emulator = self.lock()?;
+            *emulator = std::mem::take(&mut *emulator).override_quirks(quirks);
+            Ok(())
+        }
+
+        /// The whole of memory
+        fn memory<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+            Ok(PyBytes::new(py, self.lock()?.memory()))
+        }
+
+        /// A copy of the display
+        fn framebuffer(&self, py: Python<'_>) -> PyResult<Framebuffer> {
+            let emulator = self.lock()?;
+            let display = emulator.window();
+            let (width, height) = (display.width(), display.height());
+
+            let pixels: Vec<u8> = (0..width * height)
+                .map(|index| display.get_pixel(index % width, index / width).into())
+                .collect();
+            Ok(Framebuffer {
+                width,
+                height,
+                data: PyBytes::new(py, &pixels).unbind(),
+            })
+        }
+
+        /// Takes a snapshot of the machine, without the emulator's quirks
+        fn save_state(&self) -> PyResult<State> {
+            Ok(State(self.lock()?.machine().clone()))
+        }
+
+        /// Restores a snapshot taken by `save_state`
+        fn load_state(&self, state: &State) -> PyResult<()> {
+            self.lock()?.set_machine(state.0.clone());
+            Ok(())
+        }
+    }
+
+    /// A copy of the display, with one byte per pixel that is 1 if the pixel is on and 0 if it's
+    /// off, row by row from the top left.
+    ///
+    /// Implements NumPy's array interface, so `numpy.asarray(framebuffer)` gives a `height` by
+    /// `width` array of `uint8` without copying.
+    #[pyclass(frozen)]
+    struct Framebuffer {
+        #[pyo3(get)]
+        width: usize,
+        #[pyo3(get)]
+        height: usize,
+        /// The pixels
+        #[pyo3(get)]
+        data: Py<PyBytes>,
+    }
+
+    #[pymethods]
+    impl Framebuffer {
+        #[getter]
+        fn __array_interface__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+            let interface = PyDict::new(py);
+            interface.set_item("shape", (self.height, self.width))?;
+            interface.set_item("typestr", "|u1")?;
+            interface.set_item("data", self.data.bind(py))?;
+            interface.set_item("version", 3)?;
+            Ok(interface)
+        }
+
+        /// Whether the pixel at `(x, y)` is on
+        fn get_pixel(&self, py: Python<'_>, x: usize, y: usize) -> PyResult<bool> {
+            if x >= self.width || y >= self.height {
+                return Err(PyValueError::new_err(format!(
+                    "({}, {}) is outside of the {}x{} display",
+                    x, y, self.width, self.height
+                )));
+            }
+            Ok(self.data.bind(py).as_bytes()[y * self.width + x] != 0)
+        }
+    }
+
+    /// A snapshot of the machine taken by `Chip8.save_state`.
+    ///
+    /// Snapshots are cheap to take and restore, so they can be used to search through possible
+    /// inputs. They can be compared and hashed to find states that have already been seen, and
+    /// can be pickled or converted to JSON.
+    #[pyclass(frozen, eq, hash)]
+    #[derive(PartialEq, Hash)]
+    struct State(Machine);
+
+    #[pymethods]
+    impl State {
+        /// The snapshot in the JSON format of the control protocol's `save_state` command
+        fn to_json(&self) -> PyResult<String> {
+            serde_json::to_string(&SaveState::from(&self.0))
+                .map_err(|e| PyValueError::new_err(e.to_string()))
+        }
+
+        /// Reads a snapshot written by `to_json`
+        #[staticmethod]
+        fn from_json(json: &str) -> PyResult<Self> {
+            let state: SaveState = serde_json::from_str(json)
+                .map_err(|e| PyValueError::new_err(format!("Invalid state: {}", e)))?;
+            Machine::try_from(&state)
+                .map(Self)
+                .map_err(PyValueError::new_err)
+        }
+
+        fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+            let from_json = py.get_type::<Self>().getattr("from_json")?;
+            Ok((from_json, (self.to_json()?,)))
+        }
+    }
+}
@@ -0,0 +1,114 @@
+"""Tests for the Python bindings, run with `pytest` after `maturin develop`."""
+
+import pickle
+
+import pytest
+
+import chip8
+
+# adds 1 to v0 in a loop while key 5 is held
+HOLD_TO_COUNT = bytes([0x61, 0x05, 0xE1, 0xA1, 0x70, 0x01, 0x12, 0x02])
+
+# draws the font's 0 at (0, 0), then halts
+DRAW_ZERO = bytes([0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06])
+
+
+def test_keys_are_held_between_frames():
+    emulator = chip8.Chip8()
+    emulator.load_rom(HOLD_TO_COUNT)
+    emulator.run_frames(10)
+    assert emulator.registers[0] == 0
+
+    emulator.press_key(5)
+    emulator.run_frames(10)
+    assert emulator.registers[0] > 0
+
+    with pytest.raises(ValueError):
+        emulator.press_key(16)
+
+
+def test_step_runs_single_instructions():
+    emulator = chip8.Chip8()
+    emulator.load_rom(DRAW_ZERO)
+    emulator.step(2)
+    assert emulator.program_counter == 0x204
+    assert emulator.index_register == 0
+    assert emulator.frame == 0
+
+
+def test_framebuffer_is_row_major():
+    emulator = chip8.Chip8()
+    emulator.load_rom(DRAW_ZERO)
+    emulator.run_frames()
+
+    framebuffer = emulator.framebuffer()
+    assert (framebuffer.width, framebuffer.height) == (64, 32)
+    assert framebuffer.data[:5] == bytes([1, 1, 1, 1, 0])
+    assert framebuffer.get_pixel(0, 1) and not framebuffer.get_pixel(1, 1)
+    for x, y in [(64, 0), (0, 32), (2**63, 2**63)]:
+        with pytest.raises(ValueError):
+            framebuffer.get_pixel(x, y)
+
+    interface = framebuffer.__array_interface__
+    assert interface["shape"] == (32, 64)
+    assert interface["typestr"] == "|u1"
+
+
+def test_framebuffer_converts_to_numpy():
+    np = pytest.importorskip("numpy")
+    emulator = chip8.Chip8()
+    emulator.load_rom(DRAW_ZERO)
+    emulator.run_frames()
+
+    pixels = np.asarray(emulator.framebuffer())
+    assert pixels.shape == (32, 64)
+    assert pixels[:5, :4].tolist() == [
+        [1, 1, 1, 1],
+        [1, 0, 0, 1],
+        [1, 0, 0, 1],
+        [1, 0, 0, 1],
+        [1, 1, 1, 1],
+    ]
+
+
+def test_states_restore_and_deduplicate():
+    emulator = chip8.Chip8()
+    emulator.load_rom(HOLD_TO_COUNT)
+    emulator.press_key(5)
+    saved = emulator.save_state()
+
+    emulator.run_frames(10)
+    first = emulator.save_state()
+    assert first != saved
+
+    emulator.load_state(saved)
+    emulator.run_frames(10)
+    assert emulator.save_state() == first
+    assert len({saved, first, emulator.save_state()}) == 2
+
+
+def test_states_serialize():
+    emulator = chip8.Chip8()
+    emulator.load_rom(DRAW_ZERO)
+    emulator.run_frames()
+    state = emulator.save_state()
+
+    assert chip8.State.from_json(state.to_json()) == state
+    assert pickle.loads(pickle.dumps(state)) == state
+    with pytest.raises(ValueError):
+        chip8.State.from_json("{}")
+
+
+def test_quirks_accept_profiles_and_flags():
+    assert chip8.Chip8().quirks == chip8.PROFILES["chip8"]
+    assert chip8.Chip8("schip").quirks == chip8.PROFILES["schip"]
+
+    emulator = chip8.Chip8(chip8.SHIFTING | chip8.JUMPING)
+    assert emulator.quirks == chip8.SHIFTING | chip8.JUMPING
+    emulator.quirks = "xochip"
+    assert emulator.quirks == chip8.MEMORY
+
+    with pytest.raises(ValueError):
+        chip8.Chip8("chip48")
+    with pytest.raises(ValueError):
+        chip8.Chip8(0xFF)
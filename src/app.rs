@@ -1,24 +1,79 @@
-use std::{
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{collections::HashSet, path::Path, sync::Arc};
 
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
     application::ApplicationHandler,
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::{ElementState, KeyEvent, WindowEvent},
-    event_loop::ActiveEventLoop,
-    keyboard::{Key, NamedKey, SmolStr},
-    window::{Window, WindowId},
+    event_loop::{ActiveEventLoop, EventLoopProxy},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Fullscreen, Window, WindowId},
+};
+
+use crate::{
+    config::Config,
+    control::{ControlAddress, ControlServer},
+    emulation::{Command, EmulationThread, EmulatorEvent},
+    emulator::{Chip8, Quirks},
+    keymap::KeyMap,
+    palette::{self, Palette},
+    render::Renderer,
+    virtual_buffer::VirtualDisplay,
+    wav::WavWriter,
 };
 
-use crate::emulator::{Chip8, Quirks};
+#[cfg(feature = "audio")]
+use crate::sound::Speaker;
+
+/// The title of the application window
+const WINDOW_TITLE: &str = "CHIP-8 Emulator";
+/// The number of window pixels per CHIP-8 pixel when the window is created
+const WINDOW_SCALE: u32 = 20;
+/// Hotkey that switches to the next palette
+const PALETTE_HOTKEY: KeyCode = KeyCode::F2;
+/// Hotkey that mutes and unmutes the beep
+const MUTE_HOTKEY: KeyCode = KeyCode::F3;
+/// Hotkey that toggles fullscreen
+const FULLSCREEN_HOTKEY: KeyCode = KeyCode::F11;
+/// File extensions accepted when a ROM is dropped onto the window
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
 
-/// Emulated CPU should default to a rate of 700Hz
-const TARGET_CPU_FREQ: u64 = 700;
-/// Timers should be ticked at a rate of 60Hz
-const TIMER_FREQ: u64 = 60;
+/// Message shown on the display while no ROM is loaded
+const DROP_ROM_MESSAGE: [&str; 12] = [
+    "##..##..###.###......#......##..###.#.#",
+    "#.#.#.#.#.#.#.#.....#.#.....#.#.#.#.###",
+    "#.#.##..#.#.###.....###.....##..#.#.###",
+    "#.#.#.#.#.#.#.......#.#.....#.#.#.#.#.#",
+    "##..#.#.###.#.......#.#.....#.#.###.#.#",
+    ".......................................",
+    ".......................................",
+    "............#.#.###.##..###............",
+    "............#.#.#...#.#.#..............",
+    "............###.##..##..##.............",
+    "............#.#.#...#.#.#..............",
+    "............#.#.###.#.#.###............",
+];
+
+/// A CHIP-8 program read from disk
+pub struct Rom {
+    /// The ROM's file name, used for the window title and per-ROM settings
+    pub name: String,
+    /// The program data
+    pub data: Vec<u8>,
+}
+
+impl Rom {
+    /// Reads a ROM from the given path
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => path.display().to_string(),
+        };
+
+        Ok(Self { name, data })
+    }
+}
 
 /// The Application GUI
 pub struct App {
@@ -26,92 +81,326 @@ pub struct App {
     window: Option<Arc<Window>>,
     /// The application's rendering plane
     pixels: Option<Pixels<'static>>,
-    /// The emulator
-    emulator: Chip8,
-    /// The last time the CPU was ticked. Used for frequency emulation.
-    last_cpu_time: Instant,
-    /// The last time the timers were ticked. Used for frequency emulation.
-    last_timer_time: Instant,
+    /// Renders the display into [`Self::pixels`]
+    renderer: Renderer,
+    /// Runs the emulator
+    emulation: EmulationThread,
+    /// Lets external tools control the emulator, if enabled
+    control: Option<ControlServer>,
+    /// The display as of the last completed frame
+    display: VirtualDisplay,
+    /// The user's settings
+    config: Config,
+    /// The key map for the currently loaded ROM
+    keymap: KeyMap,
+    /// Physical keys that are currently held down and mapped to a CHIP-8 key
+    held_keys: HashSet<KeyCode>,
+    /// Palettes that can be switched between, starting with the configured one
+    palettes: Vec<Palette>,
+    /// Index into [`Self::palettes`] of the palette currently in use
+    palette_index: usize,
+    /// The file name of the loaded ROM. The emulator is not ticked until a ROM is loaded.
+    rom_name: Option<String>,
 }
 
 impl App {
-    /// Construct a new application with given ROM data
-    pub fn new(program_data: Vec<u8>, quirks: Option<Quirks>) -> Self {
-        let mut emulator = Chip8::new();
+    /// Construct a new application with the given ROM.
+    ///
+    /// If no ROM is given, a message prompting the user to drop a ROM onto the window is
+    /// displayed instead. Completed frames are sent to the event loop through `proxy`.
+    pub fn new(
+        rom: Option<Rom>,
+        quirks: Option<Quirks>,
+        config: Config,
+        proxy: EventLoopProxy<EmulatorEvent>,
+    ) -> Self {
+        // games shouldn't play out the same way every time they're started
+        let mut emulator = Chip8::with_tone(config.tone()).with_seed(rand::random());
+        #[cfg(feature = "audio")]
+        if let Some(speaker) = Speaker::new() {
+            emulator.add_audio_sink(Box::new(speaker));
+        }
+
         if let Some(q) = quirks {
             emulator = emulator.override_quirks(q);
         }
 
-        emulator.load(&program_data);
+        if config.decode_cache() {
+            emulator = emulator.with_decode_cache();
+        }
+
+        if config.muted() {
+            emulator.toggle_mute();
+        }
+
+        let mut display = VirtualDisplay::new();
+        let rom_name = match rom {
+            Some(rom) => {
+                emulator.load(&rom.data);
+                Some(rom.name)
+            }
+            None => {
+                let width = DROP_ROM_MESSAGE[0].len();
+                let height = DROP_ROM_MESSAGE.len();
+                display.draw_bitmap(
+                    (display.width() - width) / 2,
+                    (display.height() - height) / 2,
+                    &DROP_ROM_MESSAGE,
+                );
+                None
+            }
+        };
+
+        let on_frame = move |display: &VirtualDisplay| {
+            proxy
+                .send_event(EmulatorEvent::Frame(display.clone()))
+                .is_ok()
+        };
+        let emulation = match EmulationThread::spawn(emulator, rom_name.is_some(), on_frame) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error starting emulation thread: {:?}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // the configured palette comes first, followed by every preset that differs from it
+        let configured_palette = config.palette();
+        let palettes = std::iter::once(configured_palette)
+            .chain(
+                palette::PRESETS
+                    .iter()
+                    .map(|&(_, preset)| preset)
+                    .filter(|&preset| preset != configured_palette),
+            )
+            .collect();
 
         Self {
             window: None,
             pixels: None,
-            emulator,
-            last_cpu_time: Instant::now(),
-            last_timer_time: Instant::now(),
+            renderer: Renderer::new(
+                &display,
+                config.scaling(),
+                config.filter(),
+                config.persistence(),
+            ),
+            emulation,
+            control: None,
+            display,
+            keymap: config.keymap(rom_name.as_deref()),
+            palettes,
+            palette_index: 0,
+            config,
+            held_keys: HashSet::new(),
+            rom_name,
+        }
+    }
+
+    /// Starts recording the emulator's audio to a WAV file
+    pub fn record_audio(&mut self, path: &Path) -> std::io::Result<()> {
+        let recorder = WavWriter::create(path)?;
+        self.emulation
+            .send(Command::AddAudioSink(Box::new(recorder)));
+        Ok(())
+    }
+
+    /// Lets external tools control the emulator through a socket. See [`crate::control`].
+    pub fn serve_control(&mut self, address: &ControlAddress) -> std::io::Result<()> {
+        let Some(commands) = self.emulation.sender() else {
+            return Err(std::io::Error::other("Emulation thread has stopped"));
+        };
+        self.control = Some(ControlServer::spawn(address, commands)?);
+        Ok(())
+    }
+
+    /// The window title, including the name of the loaded ROM
+    fn title(&self) -> String {
+        match &self.rom_name {
+            Some(name) => format!("{} - {}", WINDOW_TITLE, name),
+            None => WINDOW_TITLE.to_string(),
+        }
+    }
+
+    /// Resets the emulator and runs the ROM at the given path.
+    ///
+    /// Files without a known CHIP-8 extension or that can't be read are ignored, leaving the
+    /// current program running.
+    fn load_rom_file(&mut self, path: &Path) {
+        let has_rom_extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        if !has_rom_extension {
+            log::warn!(
+                "Ignoring dropped file without a ROM extension ({}): {}",
+                ROM_EXTENSIONS.join(", "),
+                path.display()
+            );
+            return;
+        }
+
+        log::info!("Loading program from: {}", path.display());
+        let rom = match Rom::read(path) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error reading dropped file: {:?}", e);
+                return;
+            }
+        };
+
+        self.emulation.send(Command::LoadRom(rom.data));
+        self.keymap = self.config.keymap(Some(&rom.name));
+        self.held_keys.clear();
+        self.rom_name = Some(rom.name);
+
+        if let Some(window) = &self.window {
+            window.set_title(&self.title());
+        }
+        self.request_redraw();
+    }
+
+    /// Forwards a physical key press or release to the emulator.
+    ///
+    /// A CHIP-8 key is only released once every physical key bound to it has been released.
+    fn handle_key(&mut self, code: KeyCode, state: ElementState) {
+        let Some(key_index) = self.keymap.get(code) else {
+            return;
+        };
+
+        match state {
+            ElementState::Pressed => {
+                self.held_keys.insert(code);
+                self.emulation.send(Command::PressKey(key_index));
+            }
+            ElementState::Released => {
+                self.held_keys.remove(&code);
+                let still_held = self
+                    .held_keys
+                    .iter()
+                    .any(|&held| self.keymap.get(held) == Some(key_index));
+                if !still_held {
+                    self.emulation.send(Command::ReleaseKey(key_index));
+                }
+            }
+        }
+    }
+
+    /// Switches to the next palette, wrapping back around to the configured one
+    fn cycle_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        log::info!(
+            "Switching to palette: {:?}",
+            self.palettes[self.palette_index]
+        );
+        self.request_redraw();
+    }
+
+    /// Asks for the window to be redrawn. Redraws only happen when something has changed.
+    fn request_redraw(&self) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
+
+    /// Toggles borderless fullscreen on the current monitor
+    fn toggle_fullscreen(&self) {
+        if let Some(window) = &self.window {
+            let fullscreen = match window.fullscreen() {
+                Some(_) => None,
+                None => Some(Fullscreen::Borderless(None)),
+            };
+            log::debug!("Setting fullscreen: {:?}", fullscreen);
+            window.set_fullscreen(fullscreen);
+        }
+    }
+
+    /// Resizes the rendering surface to a new window size, along with the pixel buffer when the
+    /// scaling mode depends on the window size
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        // the surface can't be zero sized, which happens while minimized
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        let Some(pixels) = &mut self.pixels else {
+            return;
+        };
+
+        log::debug!("Resizing surface to {}x{}", size.width, size.height);
+        if let Err(e) = pixels.resize_surface(size.width, size.height) {
+            log::error!("Error resizing surface: {:?}", e);
+            return;
+        }
+
+        let (width, height) = self.renderer.buffer_size_for((size.width, size.height));
+        if let Err(e) = pixels.resize_buffer(width, height) {
+            log::error!("Error resizing pixel buffer: {:?}", e);
+            return;
         }
+
+        self.renderer.set_buffer_size((width, height));
+        self.request_redraw();
+    }
+
+    /// Releases every held key, e.g. when the window loses focus and won't see the key releases
+    fn release_all_keys(&mut self) {
+        self.held_keys.clear();
+        self.emulation.send(Command::ReleaseAllKeys);
     }
 
-    /// Renders the virtual window to the [`Self::pixels`] plane. Actual redrawing is deferred to
-    /// [`Self::about_to_wait`]
+    /// Renders the latest frame to the [`Self::pixels`] plane. Redraws are requested whenever
+    /// the emulation thread completes a frame
     fn draw(&mut self) {
         if let Some(pixels) = &mut self.pixels {
-            let frame = pixels.frame_mut();
-            self.emulator.window().render_to_buffer(frame);
+            self.renderer.render(
+                &self.display,
+                &self.palettes[self.palette_index],
+                pixels.frame_mut(),
+            );
 
             if let Err(e) = pixels.render() {
                 log::error!("Rending failed: {:?}", e);
             }
         }
     }
-
-    /// Maps a given character to a CHIP-8 key index
-    fn map_key_to_index(key_text: SmolStr) -> Option<usize> {
-        match key_text.as_ref() {
-            "1" => Some(0x1),
-            "2" => Some(0x2),
-            "3" => Some(0x3),
-            "4" => Some(0xC),
-            "q" => Some(0x4),
-            "w" => Some(0x5),
-            "e" => Some(0x6),
-            "r" => Some(0xD),
-            "a" => Some(0x7),
-            "s" => Some(0x8),
-            "d" => Some(0x9),
-            "f" => Some(0xE),
-            "z" => Some(0xA),
-            "x" => Some(0x0),
-            "c" => Some(0xB),
-            "v" => Some(0xF),
-            _ => None,
-        }
-    }
 }
 
-impl ApplicationHandler for App {
+impl ApplicationHandler<EmulatorEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        // Construct the default window and pixels rendering plane
-        let emu_window = self.emulator.window();
-        let width = emu_window.scaled_width() as u32;
-        let height = emu_window.scaled_height() as u32;
+        // Construct the default window and pixels rendering plane. The pixel buffer starts at the
+        // native CHIP-8 resolution and is resized to match the scaling mode once the window exists
+        let emu_window = &self.display;
+        let width = emu_window.width() as u32;
+        let height = emu_window.height() as u32;
 
         // The window is an Arc in order to have an owned shared reference with the pixels plane
-        log::info!("Creating window ({}x{})", width, height);
+        log::info!(
+            "Creating window ({}x{})",
+            width * WINDOW_SCALE,
+            height * WINDOW_SCALE
+        );
         let window = Arc::new(
-            match event_loop
-                .create_window(
-                    Window::default_attributes()
-                        .with_title("CHIP-8 Emulator")
-                        .with_inner_size(LogicalSize::new(width, height)),
-                ) {
-                    Ok(w) => w,
-                    Err(e) => {
-                        log::error!("Error constructing window: {:?}", e);
-                        std::process::exit(1);
-                    },
+            match event_loop.create_window(
+                Window::default_attributes()
+                    .with_title(self.title())
+                    .with_inner_size(LogicalSize::new(
+                        width * WINDOW_SCALE,
+                        height * WINDOW_SCALE,
+                    ))
+                    .with_min_inner_size(LogicalSize::new(width, height))
+                    .with_resizable(true)
+                    .with_fullscreen(
+                        self.config
+                            .fullscreen()
+                            .then_some(Fullscreen::Borderless(None)),
+                    ),
+            ) {
+                Ok(w) => w,
+                Err(e) => {
+                    log::error!("Error constructing window: {:?}", e);
+                    std::process::exit(1);
                 }
+            },
         );
 
         let size = window.inner_size();
@@ -121,15 +410,13 @@ impl ApplicationHandler for App {
             Err(e) => {
                 log::error!("Error constructing pixel buffer: {:?}", e);
                 std::process::exit(1);
-            },
+            }
         };
 
         self.pixels = Some(pixels);
+        self.resize(window.inner_size());
         self.window = Some(window);
-
-        // reset the cpu and timer times
-        self.last_cpu_time = Instant::now();
-        self.last_timer_time = Instant::now();
+        self.request_redraw();
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
@@ -143,24 +430,52 @@ impl ApplicationHandler for App {
                     // filter for non-repeated keypresses
                     KeyEvent {
                         state,
-                        logical_key,
+                        physical_key,
                         repeat: false,
                         ..
                     },
                 ..
             } => {
-                log::trace!("Keyboard Input: {:?}, {:?}", logical_key, state);
-                if let Key::Named(NamedKey::Escape) = logical_key {
+                log::trace!("Keyboard Input: {:?}, {:?}", physical_key, state);
+                if let PhysicalKey::Code(KeyCode::Escape) = physical_key {
                     // close the application on escape
                     event_loop.exit();
-                } else if let Key::Character(str) = logical_key
-                    && let Some(key_index) = Self::map_key_to_index(str) {
-                        match state {
-                            ElementState::Pressed => self.emulator.press_key(key_index),
-                            ElementState::Released => self.emulator.release_key(key_index),
-                        }
+                } else if let PhysicalKey::Code(PALETTE_HOTKEY) = physical_key {
+                    if state == ElementState::Pressed {
+                        self.cycle_palette();
+                    }
+                } else if let PhysicalKey::Code(MUTE_HOTKEY) = physical_key {
+                    if state == ElementState::Pressed {
+                        self.emulation.send(Command::ToggleMute);
+                    }
+                } else if let PhysicalKey::Code(FULLSCREEN_HOTKEY) = physical_key {
+                    if state == ElementState::Pressed {
+                        self.toggle_fullscreen();
+                    }
+                } else if self.rom_name.is_some()
+                    && let PhysicalKey::Code(code) = physical_key {
+                        self.handle_key(code, state);
                 }
             }
+            WindowEvent::Focused(false) => {
+                // key releases won't be delivered while unfocused, so don't leave any held
+                log::debug!("Window lost focus, releasing all keys");
+                self.release_all_keys();
+            }
+            WindowEvent::Resized(size) => {
+                self.resize(size);
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                // the physical size changes along with the scale factor
+                log::debug!("Scale factor changed to {}", scale_factor);
+                if let Some(window) = &self.window {
+                    let size = window.inner_size();
+                    self.resize(size);
+                }
+            }
+            WindowEvent::DroppedFile(path) => {
+                self.load_rom_file(&path);
+            }
             WindowEvent::RedrawRequested => {
                 self.draw();
             }
@@ -168,24 +483,13 @@ impl ApplicationHandler for App {
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        // CPU clock timer
-        let cpu_time = Duration::from_secs_f64(1.0 / TARGET_CPU_FREQ as f64);
-        while self.last_cpu_time.elapsed() >= cpu_time {
-            self.emulator.tick_cpu();
-            self.last_cpu_time += cpu_time;
-        }
-
-        // Timers run at 60Hz
-        let timer_time = Duration::from_secs_f64(1.0 / TIMER_FREQ as f64);
-        if self.last_timer_time.elapsed() >= timer_time {
-            self.emulator.tick_timers();
-            self.last_timer_time = Instant::now();
-        }
-
-        // Request redraw and sleep until next event
-        if let Some(window) = &self.window {
-            window.request_redraw();
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: EmulatorEvent) {
+        match event {
+            EmulatorEvent::Frame(display) => {
+                self.renderer.update(&display);
+                self.display = display;
+                self.request_redraw();
+            }
         }
     }
 }
@@ -0,0 +1,81 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+/// Receives the sound produced by the emulator, one 60Hz frame at a time.
+///
+/// The emulator generates its audio in emulated time and hands it to every attached sink, so
+/// playing it aloud, recording it, or discarding it are all interchangeable.
+pub trait AudioSink: Debug + Send {
+    /// Receives one frame of audio.
+    ///
+    /// # Arguments
+    /// * `beeping` - Whether the sound timer was active during the frame
+    /// * `samples` - [`crate::tone::SAMPLES_PER_FRAME`] samples at [`crate::tone::SAMPLE_RATE`],
+    ///   in the range `-1.0..=1.0`
+    fn write_frame(&mut self, beeping: bool, samples: &[f32]);
+
+    /// Discards any audio that hasn't been output yet. Called when the emulator is reset.
+    fn clear(&mut self) {}
+
+    /// Mutes or unmutes the sink. Only sinks that play sound aloud need to respect this, so
+    /// recordings are unaffected.
+    fn set_muted(&mut self, _muted: bool) {}
+
+    /// Finishes the output once no more audio will be produced
+    fn finish(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink that discards all audio
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write_frame(&mut self, _beeping: bool, _samples: &[f32]) {}
+}
+
+/// The audio captured by an [`AudioRecorder`]
+#[derive(Debug, Default, Clone)]
+pub struct Recording {
+    /// Whether the sound timer was active, for each frame
+    pub beeping: Vec<bool>,
+    /// Every sample generated
+    pub samples: Vec<f32>,
+}
+
+/// A sink that keeps everything it receives in memory so that tests can inspect it.
+///
+/// Clones share the same recording, so a clone can be kept to read it after the original has
+/// been handed to the emulator.
+#[derive(Debug, Default, Clone)]
+pub struct AudioRecorder {
+    /// The audio received so far
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl AudioRecorder {
+    /// Constructs an empty recorder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the audio received so far
+    pub fn recording(&self) -> Recording {
+        match self.recording.lock() {
+            Ok(recording) => recording.clone(),
+            Err(_) => Recording::default(),
+        }
+    }
+}
+
+impl AudioSink for AudioRecorder {
+    fn write_frame(&mut self, beeping: bool, samples: &[f32]) {
+        if let Ok(mut recording) = self.recording.lock() {
+            recording.beeping.push(beeping);
+            recording.samples.extend_from_slice(samples);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    app::Rom,
+    emulator::{Chip8, Quirks, TARGET_CPU_FREQ, cycles_in_frame},
+    instruction::Instruction,
+    virtual_buffer::VirtualDisplay,
+};
+
+/// The instruction classes reported, indexed by the top nibble of the opcode
+const OPCODE_CLASSES: [&str; 16] = [
+    "0nnn  CLS, RET, SYS",
+    "1nnn  JP",
+    "2nnn  CALL",
+    "3xkk  SE",
+    "4xkk  SNE",
+    "5xy0  SE",
+    "6xkk  LD",
+    "7xkk  ADD",
+    "8xyn  ALU",
+    "9xy0  SNE",
+    "Annn  LD I",
+    "Bnnn  JP V0",
+    "Cxkk  RND",
+    "Dxyn  DRW",
+    "Exnn  SKP, SKNP",
+    "Fxnn  timers, memory, keys",
+];
+
+/// Which display backends to replay the drawing of the benchmark against
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DisplayChoice {
+    /// Every backend, to compare them
+    #[default]
+    All,
+    /// The bitset [`VirtualDisplay`] used by the emulator
+    Bitset,
+    /// A straightforward display storing one `bool` per pixel, as a baseline
+    Pixels,
+}
+
+/// Something sprites can be drawn onto, so that drawing can be timed separately from the
+/// interpreter
+trait DisplayBackend {
+    /// Clears the display
+    fn clear(&mut self);
+
+    /// Draws a sprite, returning whether any pixels collided. Behaves the same as
+    /// [`VirtualDisplay::draw_sprite`].
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clipping: bool) -> bool;
+}
+
+impl DisplayBackend for VirtualDisplay {
+    fn clear(&mut self) {
+        VirtualDisplay::clear(self);
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clipping: bool) -> bool {
+        VirtualDisplay::draw_sprite(self, x, y, sprite.len(), sprite, clipping)
+    }
+}
+
+/// A display storing one `bool` per pixel and drawing a pixel at a time
+struct PixelDisplay {
+    /// Whether each pixel is on, row by row
+    pixels: Vec<bool>,
+    /// The width in pixels
+    width: usize,
+    /// The height in pixels
+    height: usize,
+}
+
+impl PixelDisplay {
+    /// Constructs a blank display of the same size as a [`VirtualDisplay`]
+    fn new() -> Self {
+        let display = VirtualDisplay::new();
+        Self {
+            pixels: vec![false; display.width() * display.height()],
+            width: display.width(),
+            height: display.height(),
+        }
+    }
+}
+
+impl DisplayBackend for PixelDisplay {
+    fn clear(&mut self) {
+        self.pixels.fill(false);
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clipping: bool) -> bool {
+        let (x, y) = (x % self.width, y % self.height);
+        let mut collision = false;
+
+        for (row, byte) in sprite.iter().enumerate() {
+            for column in 0..8 {
+                if byte & (0x80 >> column) == 0 {
+                    continue;
+                }
+
+                let (px, py) = (x + column, y + row);
+                if clipping && (px >= self.width || py >= self.height) {
+                    continue;
+                }
+
+                let pixel = &mut self.pixels[(py % self.height) * self.width + px % self.width];
+                collision |= *pixel;
+                *pixel = !*pixel;
+            }
+        }
+
+        collision
+    }
+}
+
+/// A display operation made by the program, recorded so it can be replayed
+enum DrawCall {
+    /// The display was cleared
+    Clear,
+    /// A sprite was drawn
+    Sprite {
+        x: usize,
+        y: usize,
+        sprite: Vec<u8>,
+        clipping: bool,
+    },
+}
+
+/// Runs the CPU for the given number of cycles as fast as possible, ticking the timers once
+/// every 60Hz frame's worth of cycles. `on_cycle` is called before every cycle.
+fn run_cycles(emulator: &mut Chip8, cycles: u64, mut on_cycle: impl FnMut(&mut Chip8)) {
+    let mut done = 0;
+    let mut frame = 0;
+    while done < cycles {
+        let frame_cycles = cycles_in_frame(frame).min(cycles - done);
+        for _ in 0..frame_cycles {
+            on_cycle(emulator);
+        }
+        emulator.tick_timers();
+
+        done += frame_cycles;
+        frame += 1;
+    }
+}
+
+/// Replays the recorded draw calls onto a display, returning how long it took
+fn time_drawing(display: &mut impl DisplayBackend, calls: &[DrawCall]) -> Duration {
+    let start = Instant::now();
+    for call in calls {
+        match call {
+            DrawCall::Clear => display.clear(),
+            DrawCall::Sprite {
+                x,
+                y,
+                sprite,
+                clipping,
+            } => {
+                std::hint::black_box(display.draw_sprite(*x, *y, sprite, *clipping));
+            }
+        }
+    }
+    start.elapsed()
+}
+
+/// Runs a ROM flat-out for a number of CPU cycles and prints how fast it ran.
+///
+/// The ROM is run twice. The first run measures the interpreter and counts the instructions
+/// executed by class. The second records every display operation, which are then replayed
+/// against each display backend to time drawing on its own.
+///
+/// # Arguments
+/// * `rom` - The program to run
+/// * `quirks` - Custom quirks, overriding the defaults
+/// * `cycles` - The number of CPU cycles to run for
+/// * `decode_cache` - Whether to cache decoded instructions
+/// * `display` - Which display backends to time drawing with
+pub fn run(
+    rom: &Rom,
+    quirks: Option<Quirks>,
+    cycles: u64,
+    decode_cache: bool,
+    display: DisplayChoice,
+) {
+    let new_emulator = || {
+        let mut emulator = Chip8::new();
+        if let Some(q) = quirks {
+            emulator = emulator.override_quirks(q);
+        }
+        if decode_cache {
+            emulator = emulator.with_decode_cache();
+        }
+        emulator.load(&rom.data);
+        emulator
+    };
+
+    println!(
+        "Benchmarking {} for {} cycles (decode cache {})",
+        rom.name,
+        cycles,
+        if decode_cache { "on" } else { "off" }
+    );
+
+    // first run: the interpreter on its own
+    let mut emulator = new_emulator();
+    let mut class_counts = [0u64; 16];
+    let start = Instant::now();
+    run_cycles(&mut emulator, cycles, |emulator| {
+        if let Some(instruction) = emulator.tick_cpu() {
+            class_counts[(instruction.encode() >> 12) as usize] += 1;
+        }
+    });
+    let elapsed = start.elapsed();
+
+    let executed: u64 = class_counts.iter().sum();
+    let seconds = elapsed.as_secs_f64();
+    println!();
+    println!(
+        "Executed {} instructions in {:.3}s, {} cycles spent waiting",
+        executed,
+        seconds,
+        cycles - executed
+    );
+    // the clock may not have ticked at all for very short runs
+    if seconds > 0.0 {
+        println!(
+            "{:.2} M instructions/s, {:.0}x real time",
+            executed as f64 / seconds / 1_000_000.0,
+            cycles as f64 / seconds / TARGET_CPU_FREQ as f64
+        );
+    } else {
+        println!("Too quick to measure the speed");
+    }
+
+    println!();
+    println!("Instructions by class:");
+    for (class, count) in OPCODE_CLASSES.iter().zip(class_counts) {
+        if count > 0 {
+            println!(
+                "  {:<28} {:>12} {:>6.2}%",
+                class,
+                count,
+                count as f64 * 100.0 / executed as f64
+            );
+        }
+    }
+
+    // second run: record what was drawn
+    let mut emulator = new_emulator();
+    let mut calls = Vec::new();
+    run_cycles(&mut emulator, cycles, |emulator| {
+        // the registers have to be read before the instruction runs, since it may change VF
+        let call = match emulator.next_instruction() {
+            Instruction::Clear => Some(DrawCall::Clear),
+            Instruction::Draw { x, y, rows } => {
+                // sprites running past the end of memory wrap around to the start, as when
+                // they're drawn by the interpreter
+                let memory = emulator.memory();
+                let start = emulator.index_register() as usize;
+                let sprite = (start..start + rows as usize)
+                    .map(|addr| memory[addr % memory.len()])
+                    .collect();
+                Some(DrawCall::Sprite {
+                    x: emulator.registers()[x as usize] as usize,
+                    y: emulator.registers()[y as usize] as usize,
+                    sprite,
+                    clipping: emulator.quirks().contains(Quirks::CLIPPING),
+                })
+            }
+            _ => None,
+        };
+
+        if emulator.tick_cpu().is_some()
+            && let Some(call) = call
+        {
+            calls.push(call);
+        }
+    });
+
+    println!();
+    println!("Drawing ({} display operations):", calls.len());
+    let report = |name: &str, time: Duration| {
+        if seconds > 0.0 {
+            println!(
+                "  {:<8} {:.3}s, {:.1}% of the interpreter's time",
+                name,
+                time.as_secs_f64(),
+                time.as_secs_f64() * 100.0 / seconds
+            );
+        } else {
+            println!("  {:<8} {:.3}s", name, time.as_secs_f64());
+        }
+    };
+    if matches!(display, DisplayChoice::All | DisplayChoice::Bitset) {
+        report("bitset", time_drawing(&mut VirtualDisplay::new(), &calls));
+    }
+    if matches!(display, DisplayChoice::All | DisplayChoice::Pixels) {
+        report("pixels", time_drawing(&mut PixelDisplay::new(), &calls));
+    }
+}
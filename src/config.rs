@@ -0,0 +1,234 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    filter::Filter,
+    keymap::{KeyBindings, KeyMap},
+    palette::Palette,
+    render::ScalingMode,
+    tone::{self, Tone, Waveform},
+};
+
+/// Settings that can be overridden for a specific ROM
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    /// Key bindings applied on top of the global ones
+    keys: KeyBindings,
+}
+
+/// Settings for the beep
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoundConfig {
+    /// The pitch in Hz
+    frequency: Option<f32>,
+    /// The shape of the waveform
+    waveform: Option<Waveform>,
+    /// The volume from 0.0 to 1.0
+    volume: Option<f32>,
+    /// Whether to start muted
+    muted: bool,
+}
+
+impl SoundConfig {
+    /// Checks the frequency and volume are usable, see [`tone::check_frequency`] and
+    /// [`tone::check_volume`]
+    fn validate(&self) -> Result<(), String> {
+        if let Some(frequency) = self.frequency {
+            tone::check_frequency(frequency)?;
+        }
+        if let Some(volume) = self.volume {
+            tone::check_volume(volume)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings loaded from the configuration file
+///
+/// ```toml
+/// palette = "amber"
+/// scaling = "fit"
+/// persistence = 3
+/// filter = "scanlines"
+/// fullscreen = false
+/// decode_cache = true
+///
+/// [sound]
+/// frequency = 1500.0
+/// waveform = "square"
+/// volume = 0.1
+///
+/// [keys]
+/// 4 = ["q", "a"]
+///
+/// [rom."PONG.ch8".keys]
+/// 1 = "w"
+/// C = "o"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The palette to render the display with, either a preset name or a list of hex colors
+    palette: Option<Palette>,
+    /// How the display is scaled to the window
+    scaling: Option<ScalingMode>,
+    /// Whether to start in fullscreen
+    fullscreen: bool,
+    /// The number of frames pixels take to fade out after being turned off
+    persistence: Option<u32>,
+    /// The post-processing filter applied to the display
+    filter: Option<Filter>,
+    /// Whether to cache decoded instructions
+    decode_cache: bool,
+    /// Settings for the beep
+    sound: SoundConfig,
+    /// Key bindings applied to every ROM
+    keys: KeyBindings,
+    /// Per-ROM overrides, keyed by the ROM's file name
+    rom: HashMap<String, RomConfig>,
+    /// Key bindings given on the command line, which take priority over the file
+    #[serde(skip)]
+    cli_keys: KeyBindings,
+}
+
+impl Config {
+    /// Reads and parses a TOML configuration file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read `{}`: {}", path.display(), e))?;
+
+        let config: Self = toml::from_str(&contents)
+            .map_err(|e| format!("invalid config `{}`: {}", path.display(), e))?;
+        config
+            .sound
+            .validate()
+            .map_err(|e| format!("invalid config `{}`: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Adds key bindings from the command line, overriding anything from the file
+    pub fn override_keys(&mut self, bindings: KeyBindings) {
+        self.cli_keys.extend(bindings);
+    }
+
+    /// Overrides the palette from the file
+    pub fn override_palette(&mut self, palette: Palette) {
+        self.palette = Some(palette);
+    }
+
+    /// The palette to render the display with
+    pub fn palette(&self) -> Palette {
+        self.palette.unwrap_or_default()
+    }
+
+    /// Overrides the scaling mode from the file
+    pub fn override_scaling(&mut self, scaling: ScalingMode) {
+        self.scaling = Some(scaling);
+    }
+
+    /// How the display is scaled to the window
+    pub fn scaling(&self) -> ScalingMode {
+        self.scaling.unwrap_or_default()
+    }
+
+    /// Makes the window start in fullscreen, regardless of the file
+    pub fn enable_fullscreen(&mut self) {
+        self.fullscreen = true;
+    }
+
+    /// Whether to start in fullscreen
+    pub fn fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    /// Makes the emulator cache decoded instructions, regardless of the file
+    pub fn enable_decode_cache(&mut self) {
+        self.decode_cache = true;
+    }
+
+    /// Whether the emulator caches decoded instructions
+    pub fn decode_cache(&self) -> bool {
+        self.decode_cache
+    }
+
+    /// Overrides the phosphor persistence from the file
+    pub fn override_persistence(&mut self, frames: u32) {
+        self.persistence = Some(frames);
+    }
+
+    /// The number of frames pixels take to fade out after being turned off, where `0` disables
+    /// phosphor persistence
+    pub fn persistence(&self) -> u32 {
+        self.persistence.unwrap_or(0)
+    }
+
+    /// Overrides the post-processing filter from the file
+    pub fn override_filter(&mut self, filter: Filter) {
+        self.filter = Some(filter);
+    }
+
+    /// The post-processing filter applied to the display
+    pub fn filter(&self) -> Filter {
+        self.filter.unwrap_or_default()
+    }
+
+    /// Overrides the beep's pitch from the file
+    pub fn override_frequency(&mut self, frequency: f32) {
+        self.sound.frequency = Some(frequency);
+    }
+
+    /// Overrides the beep's waveform from the file
+    pub fn override_waveform(&mut self, waveform: Waveform) {
+        self.sound.waveform = Some(waveform);
+    }
+
+    /// Overrides the beep's volume from the file
+    pub fn override_volume(&mut self, volume: f32) {
+        self.sound.volume = Some(volume);
+    }
+
+    /// Makes the beep start muted, regardless of the file
+    pub fn enable_mute(&mut self) {
+        self.sound.muted = true;
+    }
+
+    /// The beep played while the sound timer is active
+    pub fn tone(&self) -> Tone {
+        let default = Tone::default();
+        let volume = self.sound.volume.unwrap_or(default.volume);
+        if !(0.0..=1.0).contains(&volume) {
+            log::warn!("Volume {} is outside of 0.0-1.0, clamping", volume);
+        }
+
+        Tone {
+            frequency: self.sound.frequency.unwrap_or(default.frequency),
+            waveform: self.sound.waveform.unwrap_or(default.waveform),
+            volume: volume.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Whether to start muted
+    pub fn muted(&self) -> bool {
+        self.sound.muted
+    }
+
+    /// Builds the key map for the ROM with the given file name.
+    ///
+    /// Bindings are layered from the defaults, the global `[keys]` table, the ROM's own table
+    /// and finally the command line.
+    pub fn keymap(&self, rom_name: Option<&str>) -> KeyMap {
+        let mut keymap = KeyMap::default();
+        keymap.apply(&self.keys);
+
+        if let Some(rom_config) = rom_name.and_then(|name| self.rom.get(name)) {
+            log::debug!("Applying key bindings for ROM: {:?}", rom_name);
+            keymap.apply(&rom_config.keys);
+        }
+
+        keymap.apply(&self.cli_keys);
+        keymap
+    }
+}
@@ -0,0 +1,493 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    emulation::Command,
+    emulator::{Chip8, SaveState},
+    memory::MEMORY_SIZE,
+};
+
+/// Where the control server listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAddress {
+    /// A TCP address such as `127.0.0.1:7878`
+    Tcp(String),
+    /// The path of a Unix domain socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Parses a control address, either `HOST:PORT` or `unix:PATH`.
+///
+/// The protocol has no authentication and can read files through [`Request::LoadRom`], so TCP
+/// addresses must be on the loopback interface, such as `127.0.0.1`, `[::1]`, or `localhost`.
+pub fn parse_address(s: &str) -> Result<ControlAddress, String> {
+    match s.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => Ok(ControlAddress::Unix(PathBuf::from(path))),
+        #[cfg(not(unix))]
+        Some(_) => Err("Unix sockets aren't supported on this platform".to_string()),
+        None => {
+            let loopback = match s.parse::<SocketAddr>() {
+                Ok(address) => address.ip().is_loopback(),
+                Err(_) => s
+                    .rsplit_once(':')
+                    .is_some_and(|(host, port)| host == "localhost" && port.parse::<u16>().is_ok()),
+            };
+            if loopback {
+                Ok(ControlAddress::Tcp(s.to_string()))
+            } else if s.contains(':') {
+                Err(format!(
+                    "`{}` is not a loopback address. Only local clients can connect, since the \
+                     control protocol has no authentication.",
+                    s
+                ))
+            } else {
+                Err(format!(
+                    "`{}` is not a valid control address, expected `HOST:PORT` or `unix:PATH`",
+                    s
+                ))
+            }
+        }
+    }
+}
+
+/// A request from a client, sent as one line of JSON such as `{"command": "step", "cycles": 10}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    /// Resets the emulator and loads a program, either read from `path` or given as `data`.
+    /// Doesn't change whether the emulator is running.
+    LoadRom {
+        #[serde(default)]
+        path: Option<PathBuf>,
+        #[serde(default)]
+        data: Option<Vec<u8>>,
+    },
+    /// Runs up to [`MAX_CYCLES`] CPU cycles without ticking the timers.
+    ///
+    /// Only ticking the timers ends a wait for the VBlank, so with the
+    /// [`crate::emulator::Quirks::DISPLAY_WAIT`] quirk the CPU stops after drawing a sprite until
+    /// [`Request::TickTimers`] or [`Request::RunFrames`].
+    Step {
+        #[serde(default = "default_cycles")]
+        cycles: u64,
+    },
+    /// Ticks the timers once without running the CPU, as at the end of a frame
+    TickTimers,
+    /// Runs up to [`MAX_FRAMES`] whole 60Hz frames, as fast as possible
+    RunFrames { frames: u64 },
+    /// Starts running in real time
+    Run,
+    /// Stops running in real time. The emulator can still be stepped while paused.
+    Pause,
+    /// Presses a key, `0` to `15`
+    PressKey { key: usize },
+    /// Releases a key, `0` to `15`
+    ReleaseKey { key: usize },
+    /// Reads the registers, stack, and timers
+    Registers,
+    /// Reads `length` bytes of memory starting at `start`, by default the whole of memory
+    Memory {
+        #[serde(default)]
+        start: usize,
+        #[serde(default)]
+        length: Option<usize>,
+    },
+    /// Reads the display
+    Framebuffer,
+    /// Takes a snapshot of the machine
+    SaveState,
+    /// Restores a snapshot taken by [`Request::SaveState`]
+    LoadState { state: SaveState },
+}
+
+fn default_cycles() -> u64 {
+    1
+}
+
+/// The most cycles a single [`Request::Step`] can run. Requests are carried out on the
+/// emulation thread, so a larger count would freeze the emulator and every other client.
+pub const MAX_CYCLES: u64 = 1_000_000;
+/// The most frames a single [`Request::RunFrames`] can run, ten minutes of emulated time
+pub const MAX_FRAMES: u64 = 10 * 60 * 60;
+/// The longest line a client can send, in bytes. Save states are around 25 KB.
+const MAX_LINE: usize = 1024 * 1024;
+
+/// The data returned for a successful request
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Reply {
+    Registers {
+        v_registers: [u8; 16],
+        index_register: u16,
+        program_counter: u16,
+        stack: Vec<u16>,
+        delay_timer: u8,
+        sound_timer: u8,
+    },
+    Memory {
+        start: usize,
+        data: Vec<u8>,
+    },
+    /// Each row is 16 hex digits, where the most significant bit is the leftmost pixel
+    Framebuffer {
+        width: usize,
+        height: usize,
+        rows: Vec<String>,
+    },
+    State {
+        state: SaveState,
+    },
+}
+
+/// The response to a request, sent as one line of JSON. Successful responses have `"ok": true`
+/// along with the fields of any [`Reply`], while failed ones have `"ok": false` and an `error`.
+#[derive(Debug, Serialize)]
+pub struct Response {
+    ok: bool,
+    #[serde(flatten)]
+    reply: Option<Reply>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Response {
+    fn done() -> Self {
+        Self {
+            ok: true,
+            reply: None,
+            error: None,
+        }
+    }
+
+    fn reply(reply: Reply) -> Self {
+        Self {
+            ok: true,
+            reply: Some(reply),
+            error: None,
+        }
+    }
+
+    fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            reply: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Carries out a request on the emulation thread.
+///
+/// # Arguments
+/// * `request` - The request to carry out
+/// * `emulator` - The emulator to control
+/// * `running` - Whether the emulator is running in real time, changed by [`Request::Run`] and
+///   [`Request::Pause`]
+pub fn handle(request: Request, emulator: &mut Chip8, running: &mut bool) -> Response {
+    log::debug!("Handling control request: {:?}", request);
+    handle_inner(request, emulator, running).unwrap_or_else(Response::error)
+}
+
+fn handle_inner(
+    request: Request,
+    emulator: &mut Chip8,
+    running: &mut bool,
+) -> Result<Response, String> {
+    let response = match request {
+        Request::LoadRom { path, data } => {
+            let data = match (path, data) {
+                (Some(path), None) => std::fs::read(&path)
+                    .map_err(|e| format!("Error reading `{}`: {}", path.display(), e))?,
+                (None, Some(data)) => data,
+                _ => return Err("Expected exactly one of `path` or `data`".to_string()),
+            };
+            emulator.reset();
+            emulator.load(&data);
+            Response::done()
+        }
+        Request::Step { cycles } => {
+            check_count("cycles", cycles, MAX_CYCLES)?;
+            for _ in 0..cycles {
+                emulator.tick_cpu();
+            }
+            Response::done()
+        }
+        Request::TickTimers => {
+            emulator.tick_timers();
+            Response::done()
+        }
+        Request::RunFrames { frames } => {
+            check_count("frames", frames, MAX_FRAMES)?;
+            for _ in 0..frames {
+                emulator.run_frame();
+            }
+            Response::done()
+        }
+        Request::Run => {
+            *running = true;
+            Response::done()
+        }
+        Request::Pause => {
+            *running = false;
+            Response::done()
+        }
+        Request::PressKey { key } => {
+            check_key(key)?;
+            emulator.press_key(key);
+            Response::done()
+        }
+        Request::ReleaseKey { key } => {
+            check_key(key)?;
+            emulator.release_key(key);
+            Response::done()
+        }
+        Request::Registers => {
+            let state = emulator.save_state();
+            Response::reply(Reply::Registers {
+                v_registers: state.v_registers,
+                index_register: state.index_register,
+                program_counter: state.program_counter,
+                stack: state.stack,
+                delay_timer: state.delay_timer,
+                sound_timer: state.sound_timer,
+            })
+        }
+        Request::Memory { start, length } => {
+            let length = length.unwrap_or(MEMORY_SIZE.saturating_sub(start));
+            let data = start
+                .checked_add(length)
+                .and_then(|end| emulator.memory().get(start..end))
+                .ok_or_else(|| {
+                    format!(
+                        "{} bytes at {:#x} is outside of memory ({} bytes)",
+                        length, start, MEMORY_SIZE
+                    )
+                })?;
+            Response::reply(Reply::Memory {
+                start,
+                data: data.to_vec(),
+            })
+        }
+        Request::Framebuffer => {
+            let display = emulator.window();
+            Response::reply(Reply::Framebuffer {
+                width: display.width(),
+                height: display.height(),
+                rows: display.to_hex_rows(),
+            })
+        }
+        Request::SaveState => Response::reply(Reply::State {
+            state: emulator.save_state(),
+        }),
+        Request::LoadState { state } => {
+            emulator.load_state(&state)?;
+            Response::done()
+        }
+    };
+    Ok(response)
+}
+
+/// Checks a request doesn't run for longer than `max` cycles or frames
+fn check_count(name: &str, count: u64, max: u64) -> Result<(), String> {
+    if count > max {
+        return Err(format!(
+            "Can't run {} {} in one request, the most is {}",
+            count, name, max
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a key index is in the range `0x0..=0xF`
+fn check_key(key: usize) -> Result<(), String> {
+    if key > 0xF {
+        return Err(format!("Key {} is out of range, expected 0 to 15", key));
+    }
+    Ok(())
+}
+
+/// Listens for control clients on a background thread, passing their requests to the
+/// emulation thread.
+///
+/// Each client gets its own thread, so several tools can be connected at once. Requests are
+/// carried out in the order the emulation thread receives them.
+pub struct ControlServer {
+    /// The thread accepting connections
+    handle: Option<JoinHandle<()>>,
+    /// The Unix socket to remove once the server is dropped
+    socket_path: Option<PathBuf>,
+}
+
+impl ControlServer {
+    /// Starts listening on the given address.
+    ///
+    /// # Arguments
+    /// * `address` - Where to listen
+    /// * `commands` - Sends requests to the emulation thread, see
+    ///   [`crate::emulation::EmulationThread::sender`]
+    pub fn spawn(address: &ControlAddress, commands: Sender<Command>) -> std::io::Result<Self> {
+        let builder = std::thread::Builder::new().name("control".to_string());
+        let (handle, socket_path) = match address {
+            ControlAddress::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                log::info!(
+                    "Listening for control clients on {}",
+                    listener.local_addr()?
+                );
+                let handle = builder.spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                            Ok((reader, writer)) => spawn_client(reader, writer, &commands),
+                            Err(e) => log::error!("Error accepting control client: {}", e),
+                        }
+                    }
+                })?;
+                (handle, None)
+            }
+            #[cfg(unix)]
+            ControlAddress::Unix(path) => {
+                let listener = bind_unix(path)?;
+                log::info!("Listening for control clients on {}", path.display());
+                let handle = builder.spawn(move || {
+                    for stream in listener.incoming() {
+                        match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                            Ok((reader, writer)) => spawn_client(reader, writer, &commands),
+                            Err(e) => log::error!("Error accepting control client: {}", e),
+                        }
+                    }
+                })?;
+                (handle, Some(path.clone()))
+            }
+        };
+
+        Ok(Self {
+            handle: Some(handle),
+            socket_path,
+        })
+    }
+
+    /// Blocks until the server stops, which only happens if listening fails
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            log::error!("Control server panicked");
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket_path
+            && let Err(e) = std::fs::remove_file(path)
+        {
+            log::warn!("Error removing `{}`: {}", path.display(), e);
+        }
+    }
+}
+
+/// Binds a Unix socket, replacing a socket left behind by a server that was killed
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> std::io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e)
+            if e.kind() == std::io::ErrorKind::AddrInUse
+                && std::os::unix::net::UnixStream::connect(path).is_err() =>
+        {
+            log::warn!("Removing stale socket `{}`", path.display());
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
+/// Serves a client on its own thread
+fn spawn_client(
+    reader: impl Read + Send + 'static,
+    writer: impl Write + Send + 'static,
+    commands: &Sender<Command>,
+) {
+    let commands = commands.clone();
+    let result = std::thread::Builder::new()
+        .name("control client".to_string())
+        .spawn(move || {
+            log::info!("Control client connected");
+            match serve_client(reader, writer, &commands) {
+                Ok(()) => log::info!("Control client disconnected"),
+                Err(e) => log::warn!("Control client disconnected: {}", e),
+            }
+        });
+    if let Err(e) = result {
+        log::error!("Error starting control client thread: {}", e);
+    }
+}
+
+/// Answers each line of JSON the client sends with a line of JSON, until it disconnects.
+/// Clients sending lines longer than [`MAX_LINE`] are disconnected after an error response.
+fn serve_client(
+    reader: impl Read,
+    mut writer: impl Write,
+    commands: &Sender<Command>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        // read one byte past the limit to tell a full line from one that's too long
+        let read = (&mut reader)
+            .take(MAX_LINE as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        if line.len() > MAX_LINE {
+            let error = format!("Requests can't be longer than {} bytes", MAX_LINE);
+            write_response(&mut writer, &Response::error(error))?;
+            break;
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if commands.send(Command::Control(request, reply)).is_err() {
+                    break;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => break,
+                }
+            }
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+        write_response(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+/// Sends a response as one line of JSON
+fn write_response(mut writer: impl Write, response: &Response) -> std::io::Result<()> {
+    serde_json::to_writer(&mut writer, response)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests;
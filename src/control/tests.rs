@@ -0,0 +1,234 @@
+use serde_json::{Value, json};
+
+use super::*;
+use crate::emulator::Quirks;
+
+/// Draws a sprite, then counts up in v1
+const DRAW_THEN_COUNT: [u8; 6] = [0xD0, 0x15, 0x71, 0x01, 0x12, 0x02];
+
+/// Carries out a request given as JSON, returning the response as JSON
+fn send(emulator: &mut Chip8, running: &mut bool, request: Value) -> Result<Value, String> {
+    let request: Request = serde_json::from_value(request).map_err(|e| e.to_string())?;
+    serde_json::to_value(handle(request, emulator, running)).map_err(|e| e.to_string())
+}
+
+/// Carries out a request that should succeed
+fn ok(emulator: &mut Chip8, request: Value) -> Result<Value, String> {
+    let response = send(emulator, &mut false, request.clone())?;
+    assert_eq!(response["ok"], true, "{} failed: {}", request, response);
+    Ok(response)
+}
+
+/// Carries out a request that should fail, returning the error
+fn error(emulator: &mut Chip8, request: Value) -> Result<String, String> {
+    let response = send(emulator, &mut false, request.clone())?;
+    assert_eq!(response["ok"], false, "{} succeeded: {}", request, response);
+    Ok(response["error"].as_str().unwrap_or_default().to_string())
+}
+
+#[test]
+fn keys_must_be_in_range() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    ok(&mut emulator, json!({"command": "press_key", "key": 15}))?;
+    assert!(
+        error(&mut emulator, json!({"command": "press_key", "key": 16}))?.contains("out of range")
+    );
+    assert!(
+        error(&mut emulator, json!({"command": "release_key", "key": 99}))?
+            .contains("out of range")
+    );
+    Ok(())
+}
+
+#[test]
+fn memory_reads_must_be_inside_memory() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    let response = ok(
+        &mut emulator,
+        json!({"command": "memory", "start": 0, "length": 5}),
+    )?;
+    assert_eq!(response["data"], json!([0xF0, 0x90, 0x90, 0x90, 0xF0]));
+
+    let response = ok(&mut emulator, json!({"command": "memory", "start": 4000}))?;
+    assert_eq!(response["data"].as_array().map(Vec::len), Some(96));
+
+    for (start, length) in [(4000, 97), (4096, 1), (usize::MAX, 2)] {
+        let request = json!({"command": "memory", "start": start, "length": length});
+        assert!(error(&mut emulator, request)?.contains("outside of memory"));
+    }
+    Ok(())
+}
+
+#[test]
+fn load_rom_needs_exactly_one_source() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    let both = json!({"command": "load_rom", "path": "rom.ch8", "data": [0x12, 0x00]});
+    let neither = json!({"command": "load_rom"});
+    for request in [both, neither] {
+        assert!(error(&mut emulator, request)?.contains("exactly one"));
+    }
+
+    let missing = json!({"command": "load_rom", "path": "/nonexistent/rom.ch8"});
+    assert!(error(&mut emulator, missing)?.contains("Error reading"));
+
+    ok(
+        &mut emulator,
+        json!({"command": "load_rom", "data": [0x61, 0x2A]}),
+    )?;
+    ok(&mut emulator, json!({"command": "step"}))?;
+    let response = ok(&mut emulator, json!({"command": "registers"}))?;
+    assert_eq!(response["v_registers"][1], 0x2A);
+    assert_eq!(response["program_counter"], 0x202);
+    Ok(())
+}
+
+#[test]
+fn states_round_trip() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    emulator.load(&DRAW_THEN_COUNT);
+    ok(
+        &mut emulator,
+        json!({"command": "run_frames", "frames": 10}),
+    )?;
+    let saved = ok(&mut emulator, json!({"command": "save_state"}))?;
+
+    ok(
+        &mut emulator,
+        json!({"command": "run_frames", "frames": 10}),
+    )?;
+    let later = ok(&mut emulator, json!({"command": "save_state"}))?;
+    assert_ne!(saved, later);
+
+    ok(
+        &mut emulator,
+        json!({"command": "load_state", "state": saved["state"]}),
+    )?;
+    assert_eq!(ok(&mut emulator, json!({"command": "save_state"}))?, saved);
+
+    // invalid states leave the emulator alone
+    let mut invalid = saved["state"].clone();
+    invalid["memory"] = json!([0, 1, 2]);
+    assert!(
+        error(
+            &mut emulator,
+            json!({"command": "load_state", "state": invalid})
+        )?
+        .contains("memory")
+    );
+    assert_eq!(ok(&mut emulator, json!({"command": "save_state"}))?, saved);
+    Ok(())
+}
+
+#[test]
+fn long_runs_are_rejected() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    let step = json!({"command": "step", "cycles": MAX_CYCLES + 1});
+    assert!(error(&mut emulator, step)?.contains("the most is"));
+    let frames = json!({"command": "run_frames", "frames": MAX_FRAMES + 1});
+    assert!(error(&mut emulator, frames)?.contains("the most is"));
+    assert_eq!(emulator.program_counter(), 0x200);
+    Ok(())
+}
+
+#[test]
+fn tick_timers_ends_the_display_wait() -> Result<(), String> {
+    let mut emulator = Chip8::new().override_quirks(Quirks::CHIP_8);
+    emulator.load(&DRAW_THEN_COUNT);
+    ok(&mut emulator, json!({"command": "step", "cycles": 10}))?;
+    assert_eq!(emulator.program_counter(), 0x202);
+
+    ok(&mut emulator, json!({"command": "tick_timers"}))?;
+    ok(&mut emulator, json!({"command": "step", "cycles": 3}))?;
+    assert_eq!(emulator.registers()[1], 2);
+    Ok(())
+}
+
+#[test]
+fn run_and_pause_change_running() -> Result<(), String> {
+    let mut emulator = Chip8::new();
+    let mut running = false;
+    send(&mut emulator, &mut running, json!({"command": "run"}))?;
+    assert!(running);
+    send(&mut emulator, &mut running, json!({"command": "pause"}))?;
+    assert!(!running);
+    Ok(())
+}
+
+/// Serves a client sending `input`, carrying out its requests on another thread, and returns
+/// each line of the output
+fn serve(input: &[u8]) -> Result<Vec<Value>, String> {
+    let (commands, receiver) = mpsc::channel();
+    let emulation = std::thread::spawn(move || {
+        let mut emulator = Chip8::new();
+        let mut running = false;
+        while let Ok(Command::Control(request, reply)) = receiver.recv() {
+            let _ = reply.send(handle(request, &mut emulator, &mut running));
+        }
+    });
+
+    let mut output = Vec::new();
+    let result = serve_client(input, &mut output, &commands);
+    drop(commands);
+    let _ = emulation.join();
+    result.map_err(|e| e.to_string())?;
+
+    String::from_utf8_lossy(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+        .collect()
+}
+
+#[test]
+fn clients_get_a_response_per_line() -> Result<(), String> {
+    let input = b"{\"command\": \"step\", \"cycles\": 2}\n\nnot json\n{\"command\": \"explode\"}\n{\"command\": \"registers\"}";
+    let responses = serve(input)?;
+
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0], json!({"ok": true}));
+    for response in &responses[1..3] {
+        assert_eq!(response["ok"], false);
+        assert!(
+            response["error"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("Invalid request")
+        );
+    }
+    assert_eq!(responses[3]["program_counter"], 0x204);
+    Ok(())
+}
+
+#[test]
+fn long_lines_disconnect_the_client() -> Result<(), String> {
+    let mut input = vec![b' '; MAX_LINE + 1];
+    input.extend_from_slice(b"\n{\"command\": \"registers\"}\n");
+    let responses = serve(&input)?;
+
+    assert_eq!(responses.len(), 1);
+    assert!(
+        responses[0]["error"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("longer than")
+    );
+    Ok(())
+}
+
+#[test]
+fn only_loopback_addresses_are_accepted() {
+    for address in ["127.0.0.1:7878", "[::1]:7878", "localhost:7878"] {
+        assert_eq!(
+            parse_address(address),
+            Ok(ControlAddress::Tcp(address.to_string()))
+        );
+    }
+    for address in [
+        "0.0.0.0:7878",
+        "192.168.1.2:7878",
+        "[::]:7878",
+        "example.com:7878",
+        "7878",
+    ] {
+        assert!(parse_address(address).is_err(), "{} was accepted", address);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    audio::AudioSink,
+    control::{self, Request, Response},
+    emulator::Chip8,
+    scheduler::FrameScheduler,
+    virtual_buffer::VirtualDisplay,
+};
+
+/// Commands sent from the frontend to the emulation thread
+#[derive(Debug)]
+pub enum Command {
+    /// Presses a CHIP-8 key (`0x0..=0xF`)
+    PressKey(usize),
+    /// Releases a CHIP-8 key (`0x0..=0xF`)
+    ReleaseKey(usize),
+    /// Releases every key without completing a pending key wait
+    ReleaseAllKeys,
+    /// Resets the emulator and starts running the given program
+    LoadRom(Vec<u8>),
+    /// Mutes or unmutes the beep
+    ToggleMute,
+    /// Sends the emulator's audio to another sink
+    AddAudioSink(Box<dyn AudioSink>),
+    /// Carries out a request from a control client, sending back the response
+    Control(Request, Sender<Response>),
+    /// Stops the thread, even while other senders such as control clients are still connected
+    Stop,
+}
+
+/// Events sent from the emulation thread to the event loop
+#[derive(Debug)]
+pub enum EmulatorEvent {
+    /// A frame of emulation has completed, leaving the display in this state
+    Frame(VirtualDisplay),
+}
+
+/// Runs the emulator on its own thread, so that it keeps time even while the event loop is
+/// blocked, such as while the window is being dragged or resized on some platforms.
+///
+/// The frontend controls the emulator by sending [`Command`]s, and is handed the display after
+/// every frame. Sound is generated on the
+/// emulation thread, so it also keeps playing while the event loop is blocked.
+pub struct EmulationThread {
+    /// Sends commands to the thread. Dropping it stops the thread.
+    commands: Option<Sender<Command>>,
+    /// The running thread, joined on drop
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmulationThread {
+    /// Starts running the emulator on a new thread.
+    ///
+    /// # Arguments
+    /// * `emulator` - The emulator, with any program already loaded
+    /// * `running` - Whether a program is loaded and should start running straight away.
+    ///   Otherwise the thread waits for a [`Command::LoadRom`].
+    /// * `on_frame` - Called with the display after every frame, such as to send it to the event
+    ///   loop as an [`EmulatorEvent::Frame`]. Returning `false` pauses the emulator.
+    pub fn spawn(
+        emulator: Chip8,
+        running: bool,
+        on_frame: impl FnMut(&VirtualDisplay) -> bool + Send + 'static,
+    ) -> std::io::Result<Self> {
+        let (commands, receiver) = mpsc::channel();
+        let handle = std::thread::Builder::new()
+            .name("emulation".to_string())
+            .spawn(move || run(emulator, running, &receiver, on_frame))?;
+
+        Ok(Self {
+            commands: Some(commands),
+            handle: Some(handle),
+        })
+    }
+
+    /// Sends a command to the emulation thread
+    pub fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands
+            && commands.send(command).is_err()
+        {
+            log::error!("Emulation thread has stopped, discarding command");
+        }
+    }
+
+    /// Returns another sender of commands to the thread, such as for a
+    /// [`crate::control::ControlServer`]
+    pub fn sender(&self) -> Option<Sender<Command>> {
+        self.commands.clone()
+    }
+}
+
+impl Drop for EmulationThread {
+    fn drop(&mut self) {
+        self.send(Command::Stop);
+        self.commands = None;
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            log::error!("Emulation thread panicked");
+        }
+    }
+}
+
+/// The emulation thread's main loop. Runs frames as they become due and handles commands in
+/// between, until told to stop or the command channel is closed.
+fn run(
+    mut emulator: Chip8,
+    mut running: bool,
+    commands: &Receiver<Command>,
+    mut on_frame: impl FnMut(&VirtualDisplay) -> bool,
+) {
+    let mut scheduler = FrameScheduler::new(Instant::now());
+
+    loop {
+        // sleep until the next frame is due, waking early for commands. Without a program
+        // there is nothing to do until one is loaded.
+        let command = if running {
+            let timeout = scheduler
+                .next_deadline()
+                .saturating_duration_since(Instant::now());
+            match commands.recv_timeout(timeout) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            }
+        };
+
+        match command {
+            Some(Command::PressKey(key_index)) => emulator.press_key(key_index),
+            Some(Command::ReleaseKey(key_index)) => emulator.release_key(key_index),
+            Some(Command::ReleaseAllKeys) => emulator.release_all_keys(),
+            Some(Command::LoadRom(data)) => {
+                emulator.reset();
+                emulator.load(&data);
+                // the new program starts running from a fresh clock
+                scheduler.reset(Instant::now());
+                running = true;
+            }
+            Some(Command::ToggleMute) => emulator.toggle_mute(),
+            Some(Command::AddAudioSink(sink)) => emulator.add_audio_sink(sink),
+            Some(Command::Control(request, reply)) => {
+                let was_running = running;
+                let response = control::handle(request, &mut emulator, &mut running);
+                if running && !was_running {
+                    scheduler.reset(Instant::now());
+                }
+                // the request may have changed the display, even while paused
+                if !on_frame(emulator.window()) {
+                    running = false;
+                }
+                // the client may have disconnected while waiting
+                let _ = reply.send(response);
+            }
+            Some(Command::Stop) => break,
+            None => (),
+        }
+
+        if !running {
+            continue;
+        }
+
+        for _ in 0..scheduler.due_frames(Instant::now()) {
+            emulator.run_frame();
+            if !on_frame(emulator.window()) {
+                log::debug!("Frontend has closed, stopping emulation");
+                running = false;
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = emulator.finish_audio() {
+        log::error!("Error finishing audio output: {:?}", e);
+    }
+}
@@ -1,11 +1,38 @@
-use crate::{memory::Memory, stack::Stack, virtual_buffer::VirtualDisplay};
-
-#[cfg(feature = "audio")]
-use crate::sound::Speaker;
+use std::ops::Range;
+
+use crate::{
+    audio::AudioSink,
+    instruction::{Instruction, Platform},
+    machine::{FrameCount, Machine},
+    memory::MEMORY_SIZE,
+    rng::Xorshift,
+    stack::Stack,
+    tone::{Oscillator, SAMPLES_PER_FRAME, Tone},
+    virtual_buffer::VirtualDisplay,
+};
 
 /// Where the user program should be loaded into memory, and what the program counter is
 /// initialized to
-const START_ADDR: u16 = 0x200;
+pub(crate) const START_ADDR: u16 = 0x200;
+/// Emulated CPU should default to a rate of 700Hz
+pub const TARGET_CPU_FREQ: u64 = 700;
+/// Timers should be ticked at a rate of 60Hz
+pub const TIMER_FREQ: u64 = 60;
+
+/// The number of CPU cycles in the given frame at [`TARGET_CPU_FREQ`].
+///
+/// The CPU frequency doesn't divide evenly into frames, so the number of cycles varies slightly
+/// from frame to frame to keep the average exact.
+pub const fn cycles_in_frame(frame: u64) -> u64 {
+    (frame + 1) * TARGET_CPU_FREQ / TIMER_FREQ - frame * TARGET_CPU_FREQ / TIMER_FREQ
+}
+
+/// The number of frames after which [`cycles_in_frame`] repeats itself
+pub const FRAME_PERIOD: u64 = TIMER_FREQ / gcd(TARGET_CPU_FREQ, TIMER_FREQ);
+
+const fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
 
 bitflags::bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -25,65 +52,180 @@ bitflags::bitflags! {
     }
 }
 
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter
+    pub const CHIP_8: Self = Self::VF_RESET
+        .union(Self::MEMORY)
+        .union(Self::DISPLAY_WAIT)
+        .union(Self::CLIPPING);
+    /// The behaviour of SUPER-CHIP 1.1 on the HP 48
+    pub const SUPER_CHIP: Self = Self::CLIPPING.union(Self::SHIFTING).union(Self::JUMPING);
+    /// The behaviour of XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Self = Self::MEMORY;
+
+    /// The quirk sets of every platform profile, with their names
+    pub const PROFILES: [(&'static str, Self); 3] = [
+        ("chip8", Self::CHIP_8),
+        ("schip", Self::SUPER_CHIP),
+        ("xochip", Self::XO_CHIP),
+    ];
+}
+
 impl Default for Quirks {
     fn default() -> Self {
-        Self::VF_RESET | Self::DISPLAY_WAIT | Self::CLIPPING | Self::MEMORY
+        Self::CHIP_8
     }
 }
 
+/// A snapshot of the machine's state, which can be restored with [`Chip8::load_state`].
+///
+/// Settings such as quirks and audio are not part of the state.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SaveState {
+    /// The whole of memory
+    pub memory: Vec<u8>,
+    /// The 16 VX registers
+    pub v_registers: [u8; 16],
+    pub index_register: u16,
+    pub program_counter: u16,
+    /// Return addresses on the stack, from the bottom up
+    pub stack: Vec<u16>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// The display, as returned by [`VirtualDisplay::to_hex_rows`]
+    pub display: Vec<String>,
+    pub keys: [bool; 16],
+    /// The register an `Fx0A` key wait will write to, if one is pending
+    pub key_wait_register: Option<u8>,
+    pub waiting_for_vblank: bool,
+    /// The number of frames run since the last reset
+    pub frame: u64,
+    /// The state of the random number generator used by `Cxkk`
+    pub rng: u64,
+}
+
+impl From<&Machine> for SaveState {
+    fn from(machine: &Machine) -> Self {
+        Self {
+            memory: machine.memory.as_slice().to_vec(),
+            v_registers: machine.v_registers,
+            index_register: machine.index_register,
+            program_counter: machine.program_counter,
+            stack: machine.stack.as_slice().to_vec(),
+            delay_timer: machine.delay_timer,
+            sound_timer: machine.sound_timer,
+            display: machine.window.to_hex_rows(),
+            keys: machine.keys,
+            key_wait_register: machine.key_wait_register,
+            waiting_for_vblank: machine.waiting_for_vblank,
+            frame: machine.frame.0,
+            rng: machine.rng.state(),
+        }
+    }
+}
+
+impl TryFrom<&SaveState> for Machine {
+    type Error = String;
+
+    fn try_from(state: &SaveState) -> Result<Self, Self::Error> {
+        if state.memory.len() != MEMORY_SIZE {
+            return Err(format!(
+                "Expected {} bytes of memory, got {}",
+                MEMORY_SIZE,
+                state.memory.len()
+            ));
+        }
+        if let Some(x) = state.key_wait_register
+            && x > 0xF
+        {
+            return Err(format!("Invalid key wait register: {}", x));
+        }
+
+        let mut machine = Machine::new();
+        machine.memory[0..MEMORY_SIZE].copy_from_slice(&state.memory);
+        machine.v_registers = state.v_registers;
+        machine.index_register = state.index_register;
+        machine.program_counter = state.program_counter;
+        machine.stack = Stack::from_slice(&state.stack)?;
+        machine.delay_timer = state.delay_timer;
+        machine.sound_timer = state.sound_timer;
+        machine.window = VirtualDisplay::from_hex_rows(&state.display)?;
+        machine.keys = state.keys;
+        machine.key_wait_register = state.key_wait_register;
+        machine.waiting_for_vblank = state.waiting_for_vblank;
+        machine.frame = FrameCount(state.frame);
+        machine.rng = Xorshift::from_state(state.rng)?;
+        Ok(machine)
+    }
+}
 
 /// The main emulator state
 #[derive(Debug)]
 pub struct Chip8 {
-    /// The RAM
-    memory: Memory,
-    /// 16 VX registers
-    v_registers: [u8; 16],
-    /// Delay timer
-    delay_timer: u8,
-    /// Sound timer
-    sound_timer: u8,
-    /// Program counter
-    program_counter: u16,
-    /// Special index (I) register
-    index_register: u16,
-    /// The stack
-    stack: Stack,
-    /// The virtualized window display buffer
-    window: VirtualDisplay,
-    /// Array of which keys are currently pressed
-    keys: [bool; 16],
-    /// This is Some when we are waiting on a keypress from the FX0A instruction
-    key_wait_register: Option<u8>,
-    /// Signifies when we are waiting for the next VBlank, see [`Quirks::DISPLAY_WAIT`]
-    waiting_for_vblank: bool,
-    /// Optional audio support
-    #[cfg(feature = "audio")]
-    speaker: Option<Speaker>,
+    /// The registers, memory, display, and everything else the program can see
+    machine: Machine,
+    /// The length in instructions of the loop the program is spinning in, when it can't exit
+    /// before the next timer tick. See [`Self::idle_loop_len`]
+    idle_loop: Option<u16>,
+    /// Generates the beep's samples as emulated time passes
+    oscillator: Oscillator,
+    /// Scratch buffer holding the samples of the current frame
+    frame_samples: Vec<f32>,
+    /// Where the generated audio goes. No audio is generated if there are none.
+    audio_sinks: Vec<Box<dyn AudioSink>>,
+    /// Whether the audio sinks are muted
+    muted: bool,
+    /// The variant of CHIP-8 whose instruction set is used
+    platform: Platform,
+    /// Instructions that have already been decoded, indexed by address. `None` unless enabled
+    /// with [`Self::with_decode_cache`]
+    decode_cache: Option<Vec<Option<Instruction>>>,
     quirks: Quirks,
 }
 
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Chip8 {
     /// Creates a new CHIP-8 emulator with default values
     pub fn new() -> Self {
+        Self::with_tone(Tone::default())
+    }
+
+    /// Creates a new CHIP-8 emulator that beeps with the given tone.
+    ///
+    /// The sound goes nowhere until an [`AudioSink`] is added with [`Self::add_audio_sink`].
+    pub fn with_tone(tone: Tone) -> Self {
         Self {
-            window: VirtualDisplay::new(20),
-            v_registers: [0; 16],
-            delay_timer: 0,
-            sound_timer: 0,
-            program_counter: START_ADDR,
-            index_register: 0,
-            stack: Stack::new(),
-            memory: Memory::new(),
-            keys: [false; 16],
-            key_wait_register: None,
-            waiting_for_vblank: false,
-            #[cfg(feature = "audio")]
-            speaker: Speaker::new(),
+            machine: Machine::new(),
+            idle_loop: None,
+            oscillator: Oscillator::new(tone),
+            frame_samples: Vec::with_capacity(SAMPLES_PER_FRAME),
+            audio_sinks: Vec::new(),
+            muted: false,
+            platform: Platform::default(),
+            decode_cache: None,
             quirks: Default::default(),
         }
     }
 
+    /// Sends the sound the program produces to another sink, in addition to any already added
+    pub fn add_audio_sink(&mut self, mut sink: Box<dyn AudioSink>) {
+        sink.set_muted(self.muted);
+        self.audio_sinks.push(sink);
+    }
+
+    /// Finishes the output of every audio sink, returning the first error
+    pub fn finish_audio(&mut self) -> std::io::Result<()> {
+        self.audio_sinks
+            .iter_mut()
+            .map(|sink| sink.finish())
+            .fold(Ok(()), Result::and)
+    }
+
     /// Consumes self and adds the given quirks, overriding anything set in the previous self
     #[allow(dead_code)]
     pub fn override_quirks(mut self, quirks: Quirks) -> Self {
@@ -91,6 +233,27 @@ impl Chip8 {
         self
     }
 
+    /// Consumes self and caches decoded instructions by address, so that instructions run
+    /// repeatedly don't need decoding every time. Cached instructions are invalidated when the
+    /// program writes to their memory.
+    pub fn with_decode_cache(mut self) -> Self {
+        self.decode_cache = Some(vec![None; MEMORY_SIZE]);
+        self
+    }
+
+    /// Consumes self and seeds the random number generator used by `Cxkk`. Emulators are seeded
+    /// with 0 unless given another seed, so they generate the same numbers every time.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed_rng(seed);
+        self
+    }
+
+    /// Seeds the random number generator used by `Cxkk`. The generator is part of the
+    /// [`Machine`], so it's saved and restored along with the rest of the machine.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.machine.rng = Xorshift::new(seed);
+    }
+
     /// Consumes self and adds the given additional quirks on top of the default ones
     #[allow(dead_code)]
     pub fn add_quirks(mut self, quirks: Quirks) -> Self {
@@ -100,28 +263,163 @@ impl Chip8 {
 
     /// Returns a reference to the held window
     pub fn window(&self) -> &VirtualDisplay {
-        &self.window
+        &self.machine.window
+    }
+
+    /// The VX registers
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.machine.v_registers
+    }
+
+    /// The index (I) register
+    pub fn index_register(&self) -> u16 {
+        self.machine.index_register
+    }
+
+    /// The program counter
+    pub fn program_counter(&self) -> u16 {
+        self.machine.program_counter
+    }
+
+    /// The delay timer
+    pub fn delay_timer(&self) -> u8 {
+        self.machine.delay_timer
+    }
+
+    /// The sound timer
+    pub fn sound_timer(&self) -> u8 {
+        self.machine.sound_timer
+    }
+
+    /// The whole of memory
+    pub fn memory(&self) -> &[u8] {
+        self.machine.memory.as_slice()
+    }
+
+    /// The quirks currently in use
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Mutes or unmutes the beep
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        log::info!("{} sound", if self.muted { "Muting" } else { "Unmuting" });
+        for sink in &mut self.audio_sinks {
+            sink.set_muted(self.muted);
+        }
+    }
+
+    /// Resets the machine to its power-on state, discarding any loaded program.
+    ///
+    /// Quirks and audio devices are kept so that a new program can be loaded into the same
+    /// emulator. The random number generator carries on where it left off rather than repeating
+    /// the same numbers after every reset; use [`Self::seed_rng`] to restart it.
+    pub fn reset(&mut self) {
+        log::debug!("Resetting emulator");
+        self.machine = Machine {
+            rng: self.machine.rng,
+            ..Machine::new()
+        };
+        self.invalidate_decoded(0..MEMORY_SIZE);
+        self.idle_loop = None;
+
+        self.oscillator.set_gate(false);
+        for sink in &mut self.audio_sinks {
+            sink.clear();
+        }
+    }
+
+    /// The state of the machine, which can be cloned to restore later with
+    /// [`Self::set_machine`]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Replaces the state of the machine, such as with one cloned from [`Self::machine`]
+    /// earlier. Settings such as quirks and audio are kept.
+    pub fn set_machine(&mut self, machine: Machine) {
+        let previous = std::mem::replace(&mut self.machine, machine);
+        self.idle_loop = None;
+
+        // only the instructions in memory that changed need decoding again
+        if self.decode_cache.is_some() {
+            for addr in 0..MEMORY_SIZE {
+                if previous.memory[addr] != self.machine.memory[addr] {
+                    self.invalidate_written(addr);
+                }
+            }
+        }
+    }
+
+    /// Takes a snapshot of the machine's state
+    pub fn save_state(&self) -> SaveState {
+        SaveState::from(&self.machine)
+    }
+
+    /// Restores the machine to a snapshot taken by [`Self::save_state`]. The emulator is left
+    /// unchanged if the snapshot is invalid.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), String> {
+        self.set_machine(Machine::try_from(state)?);
+        Ok(())
+    }
+
+    /// Runs one 60Hz frame: the CPU cycles that fit into the frame at [`TARGET_CPU_FREQ`],
+    /// followed by a single tick of the timers.
+    ///
+    /// See [`cycles_in_frame`]. Once the CPU is stuck until the next timer tick, whether idling
+    /// in a loop or waiting for a VBlank or key, the rest of the frame's cycles are skipped since
+    /// running them wouldn't change anything.
+    pub fn run_frame(&mut self) {
+        let cycles = cycles_in_frame(self.machine.frame.0);
+        for cycle in 0..cycles {
+            self.tick_cpu();
+
+            let skipped = cycles - cycle - 1;
+            if let Some(len) = self.idle_loop {
+                log::trace!("Idle loop detected, skipping {} cycles", skipped);
+                // leave the program counter where running the skipped cycles would have
+                self.machine.program_counter += 2 * (skipped % len as u64) as u16;
+                break;
+            }
+
+            if self.machine.waiting_for_vblank || self.machine.key_wait_register.is_some() {
+                log::trace!("CPU waiting, skipping {} cycles", skipped);
+                break;
+            }
+        }
+
+        self.tick_timers();
+        self.machine.frame.0 += 1;
     }
 
-    /// Ticks the CPU and runs the Von Neumann decode-execute cycle
+    /// Ticks the CPU and runs the Von Neumann decode-execute cycle, returning the instruction
+    /// that was executed.
     ///
     /// Note that this doesn't do anything if currently waiting on a keypress from the user. See
-    /// [`Self::key_wait_register`]
-    pub fn tick_cpu(&mut self) {
+    /// [`Self::release_key`]
+    pub fn tick_cpu(&mut self) -> Option<Instruction> {
         // don't execute anything if we're waiting on a VBlank
-        if self.waiting_for_vblank {
+        if self.machine.waiting_for_vblank {
             log::trace!("Waiting for VBlank, skipping CPU tick");
-            return;
+            return None;
         }
 
         // don't execute anything if waiting on a key release
-        if self.key_wait_register.is_some() {
+        if self.machine.key_wait_register.is_some() {
             log::trace!("Waiting for keypress, skipping CPU tick");
-            return;
+            return None;
         }
 
-        let opcode = self.fetch();
-        self.execute(opcode);
+        self.idle_loop = None;
+        let instruction = self.fetch_instruction();
+        self.execute(instruction);
+        Some(instruction)
+    }
+
+    /// Decodes the instruction at the program counter without executing it
+    pub fn next_instruction(&self) -> Instruction {
+        Instruction::decode(self.opcode_at(self.machine.program_counter), self.platform)
     }
 
     /// Register a key as currently pressed within the emulator. Accepts a key index in the range of `0x0..=0xF`
@@ -132,7 +430,7 @@ impl Chip8 {
         }
 
         log::debug!("Pressing key: {}", key_index);
-        self.keys[key_index] = true;
+        self.machine.keys[key_index] = true;
     }
 
     /// Register a key as currently released within the emulator. Accepts a key index in the range of `0x0..=0xF`
@@ -142,356 +440,450 @@ impl Chip8 {
             return;
         }
 
+        if !self.machine.keys[key_index] {
+            log::debug!("Ignoring release of key that isn't pressed: {}", key_index);
+            return;
+        }
+
         log::debug!("Releasing key: {}", key_index);
-        self.keys[key_index] = false;
+        self.machine.keys[key_index] = false;
 
-        if let Some(reg_x) = self.key_wait_register {
+        if let Some(reg_x) = self.machine.key_wait_register {
             log::debug!("Writing key index to register {}", reg_x);
-            self.v_registers[reg_x as usize] = key_index as u8;
-            self.key_wait_register = None;
+            self.machine.v_registers[reg_x as usize] = key_index as u8;
+            self.machine.key_wait_register = None;
         }
     }
 
+    /// Releases every key without completing a pending `Fx0A` key wait.
+    ///
+    /// This is used when the host loses track of the keyboard, such as when the window loses
+    /// focus, rather than for keys the user actually released.
+    pub fn release_all_keys(&mut self) {
+        log::debug!("Releasing all keys");
+        self.machine.keys = [false; 16];
+    }
+
     /// Load ROM data into the emulator. Does not clear previously loaded data.
+    ///
+    /// Programs that don't fit into memory are truncated.
     pub fn load(&mut self, data: &[u8]) {
         let start = START_ADDR as usize;
-        self.memory[start..start + data.len()].copy_from_slice(data);
+        let max_len = MEMORY_SIZE - start;
+
+        let data = if data.len() > max_len {
+            log::warn!(
+                "Program is {} bytes but only {} bytes fit in memory, truncating",
+                data.len(),
+                max_len
+            );
+            &data[..max_len]
+        } else {
+            data
+        };
+
+        self.machine.memory[start..start + data.len()].copy_from_slice(data);
+        self.invalidate_decoded(start..start + data.len());
     }
 
     /// Tick the timers if they are greater than 0. This should happen at a rate of 60Hz
     pub fn tick_timers(&mut self) {
         // since this runs at 60Hz, it will limit sprites drawn to 60/second
-        self.waiting_for_vblank = false;
+        self.machine.waiting_for_vblank = false;
+        // idle loops may exit now that the delay timer has changed
+        self.idle_loop = None;
+
+        if self.machine.delay_timer > 0 {
+            self.machine.delay_timer -= 1;
+            log::trace!(
+                "Delay timer ticked, new value: {}",
+                self.machine.delay_timer
+            );
+        }
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-            log::trace!("Delay timer ticked, new value: {}", self.delay_timer);
+        // the beep sounds for exactly as many frames as the sound timer was set to
+        let beeping = self.machine.sound_timer > 0;
+        if self.machine.sound_timer > 0 {
+            self.machine.sound_timer -= 1;
+            log::trace!(
+                "Sound timer ticked, new value: {}",
+                self.machine.sound_timer
+            );
         }
 
-        if self.sound_timer > 0 {
-            // timer is currently active
+        self.generate_audio(beeping);
+    }
 
-            #[cfg(feature = "audio")]
-            if let Some(speaker) = &mut self.speaker && !speaker.is_playing() {
-                speaker.start();
-            }
-            self.sound_timer -= 1;
-            log::trace!("Sound timer ticked, new value: {}", self.sound_timer);
-        } else {
-            // timer is not active, check if buzz is disabled
-            #[cfg(feature = "audio")]
-            if let Some(speaker) = &mut self.speaker && speaker.is_playing() {
-                speaker.stop();
-            }
+    /// Generates one frame of audio, with the beep sounding if `beeping` is set
+    fn generate_audio(&mut self, beeping: bool) {
+        // don't bother generating samples if nothing is listening
+        if self.audio_sinks.is_empty() {
+            return;
+        }
+
+        self.oscillator.set_gate(beeping);
+        self.frame_samples.clear();
+        self.frame_samples
+            .extend((0..SAMPLES_PER_FRAME).map(|_| self.oscillator.next_sample()));
+
+        for sink in &mut self.audio_sinks {
+            sink.write_frame(beeping, &self.frame_samples);
         }
     }
 
     /// Fetch the current instruction pointed to by [`Self::program_counter`] from memory
     fn fetch(&mut self) -> u16 {
-        let high = self.memory[self.program_counter as usize] as u16;
-        let low = self.memory[(self.program_counter + 1) as usize] as u16;
-        let opcode = (high << 8) | low;
-        self.program_counter += 2;
+        let opcode = self.opcode_at(self.machine.program_counter);
+        self.machine.program_counter = self.machine.program_counter % MEMORY_SIZE as u16 + 2;
 
         opcode
     }
 
-    /// Executes an instruction
-    fn execute(&mut self, opcode: u16) {
-        log::trace!("Executing opcode: 0x{:04x}", opcode);
-
-        let bit1 = (opcode & 0xF000) >> 12;
-        let bit2 = (opcode & 0x0F00) >> 8;
-        let bit3 = (opcode & 0x00F0) >> 4;
-        let bit4 = opcode & 0x000F;
-
-        match (bit1, bit2, bit3, bit4) {
-            (0, 0, 0, 0) => (),
-            (0, 0, 0xE, 0) => {
-                log::trace!("CLS");
-                self.window.clear()
-            }
-            (0, 0, 0xE, 0xE) => {
-                let addr = self.stack.pop();
-                log::trace!("RET to 0x{:04x}", addr);
-                self.program_counter = addr;
-            }
-            (1, _, _, _) => {
-                let addr = opcode & 0xFFF;
-                log::trace!("JP 0x{:04x}", addr);
-                self.program_counter = addr;
-            }
-            (2, _, _, _) => {
-                let addr = opcode & 0xFFF;
-                log::trace!("CALL 0x{:04x}", addr);
-                self.stack.push(self.program_counter);
-                self.program_counter = addr;
-            }
-            (3, reg, _, _) => {
-                let val = (opcode & 0xFF) as u8;
-                log::trace!("SE V{:X}, {}", reg, val);
-                if self.v_registers[reg as usize] == val {
-                    self.program_counter += 2;
+    /// Fetches and decodes the instruction at the program counter, using the decode cache if
+    /// enabled
+    fn fetch_instruction(&mut self) -> Instruction {
+        let addr = self.machine.program_counter as usize % MEMORY_SIZE;
+        if let Some(cache) = &self.decode_cache
+            && let Some(&Some(instruction)) = cache.get(addr)
+        {
+            self.machine.program_counter = addr as u16 + 2;
+            return instruction;
+        }
+
+        let instruction = Instruction::decode(self.fetch(), self.platform);
+        if let Some(cache) = &mut self.decode_cache
+            && let Some(entry) = cache.get_mut(addr)
+        {
+            *entry = Some(instruction);
+        }
+        instruction
+    }
+
+    /// Discards cached instructions overlapping memory that has been written to
+    fn invalidate_decoded(&mut self, written: Range<usize>) {
+        if let Some(cache) = &mut self.decode_cache {
+            // an instruction starting the byte before the write also covers its first byte
+            let start = written.start.saturating_sub(1).min(cache.len());
+            let end = written.end.min(cache.len());
+            cache[start..end].fill(None);
+        }
+    }
+
+    /// Returns the opcode stored at the given address
+    fn opcode_at(&self, addr: u16) -> u16 {
+        (self.read_memory(addr as usize) as u16) << 8 | self.read_memory(addr as usize + 1) as u16
+    }
+
+    /// Reads a byte of memory. Addresses past the end of memory wrap around to the start.
+    fn read_memory(&self, addr: usize) -> u8 {
+        self.machine.memory[addr % MEMORY_SIZE]
+    }
+
+    /// Writes a byte of memory, discarding any instruction decoded from it. Addresses past the
+    /// end of memory wrap around to the start.
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        let addr = addr % MEMORY_SIZE;
+        self.machine.memory[addr] = value;
+        self.invalidate_written(addr);
+    }
+
+    /// Discards cached instructions that include the byte at `addr`
+    fn invalidate_written(&mut self, addr: usize) {
+        self.invalidate_decoded(addr..addr + 1);
+        if addr == 0 {
+            // an instruction at the last address wraps around to include the first byte
+            self.invalidate_decoded(MEMORY_SIZE - 1..MEMORY_SIZE);
+        }
+    }
+
+    /// Checks whether jumping from `from` to `to` spins in a loop that can't exit before the
+    /// next timer tick, returning the number of instructions in the loop. Two kinds of loop are
+    /// detected:
+    /// * A jump to itself, which ROMs often use to halt once finished
+    /// * Waiting on the delay timer: `Fx07` then `3xkk` or `4xkk` on the same register, then a
+    ///   jump back to the `Fx07`. The delay timer only changes when the timers tick.
+    fn idle_loop_len(&self, from: u16, to: u16) -> Option<u16> {
+        if from == to {
+            return Some(1);
+        }
+
+        if to.checked_add(4) != Some(from) {
+            return None;
+        }
+
+        let load = Instruction::decode(self.opcode_at(to), self.platform);
+        let skip = Instruction::decode(self.opcode_at(to + 2), self.platform);
+
+        // the register must already hold the current delay timer, otherwise the loop's next
+        // iteration could see a different value
+        let waiting = match (load, skip) {
+            (
+                Instruction::LoadDelayTimer { x },
+                Instruction::SkipIfEqual { x: skip_x, .. }
+                | Instruction::SkipIfNotEqual { x: skip_x, .. },
+            ) => x == skip_x && self.machine.v_registers[x as usize] == self.machine.delay_timer,
+            _ => false,
+        };
+        waiting.then_some(3)
+    }
+
+    /// Executes a decoded instruction
+    fn execute(&mut self, instruction: Instruction) {
+        log::trace!("Executing: {}", instruction);
+
+        match instruction {
+            Instruction::Sys { addr } => {
+                // 0000 is empty memory, which some programs run through harmlessly
+                if addr != 0 {
+                    log::error!("Unimplemented opcode: 0x{:04x}", addr);
                 }
             }
-            (4, reg, _, _) => {
-                let val = (opcode & 0xFF) as u8;
-                log::trace!("SNE V{:X}, {}", reg, val);
-                if self.v_registers[reg as usize] != val {
-                    self.program_counter += 2;
+            Instruction::Clear => self.machine.window.clear(),
+            Instruction::Return => match self.machine.stack.pop() {
+                Ok(addr) => {
+                    log::trace!("Returning to 0x{:04x}", addr);
+                    self.machine.program_counter = addr;
+                }
+                Err(e) => log::error!("{}, ignoring return", e),
+            },
+            Instruction::Jump { addr } => {
+                self.idle_loop =
+                    self.idle_loop_len(self.machine.program_counter.wrapping_sub(2), addr);
+                self.machine.program_counter = addr;
+            }
+            Instruction::Call { addr } => {
+                match self.machine.stack.push(self.machine.program_counter) {
+                    Ok(()) => self.machine.program_counter = addr,
+                    Err(e) => log::error!("{}, ignoring call to 0x{:04x}", e, addr),
                 }
             }
-            (5, reg_x, reg_y, 0) => {
-                log::trace!("SE V{:X}, V{:X}", reg_x, reg_y);
-                if self.v_registers[reg_x as usize] == self.v_registers[reg_y as usize] {
-                    self.program_counter += 2;
+            Instruction::SkipIfEqual { x, value } => {
+                if self.machine.v_registers[x as usize] == value {
+                    self.machine.program_counter += 2;
                 }
             }
-            (6, reg, _, _) => {
-                let val = (opcode & 0xFF) as u8;
-                log::trace!("LD V{:X}, {}", reg, val);
-                self.v_registers[reg as usize] = val;
+            Instruction::SkipIfNotEqual { x, value } => {
+                if self.machine.v_registers[x as usize] != value {
+                    self.machine.program_counter += 2;
+                }
+            }
+            Instruction::SkipIfRegistersEqual { x, y } => {
+                if self.machine.v_registers[x as usize] == self.machine.v_registers[y as usize] {
+                    self.machine.program_counter += 2;
+                }
             }
-            (7, reg, _, _) => {
-                let val = (opcode & 0xFF) as u8;
-                log::trace!("ADD V{:X}, {}", reg, val);
-                let value = &mut self.v_registers[reg as usize];
-                *value = (*value).wrapping_add(val);
+            Instruction::Load { x, value } => {
+                self.machine.v_registers[x as usize] = value;
             }
-            (8, reg_x, reg_y, 0) => {
-                log::trace!("LD V{:X}, V{:X}", reg_x, reg_y);
-                self.v_registers[reg_x as usize] = self.v_registers[reg_y as usize];
+            Instruction::Add { x, value } => {
+                let vx = &mut self.machine.v_registers[x as usize];
+                *vx = (*vx).wrapping_add(value);
             }
-            (8, reg_x, reg_y, 1) => {
-                log::trace!("OR V{:X}, V{:X}", reg_x, reg_y);
-                self.v_registers[reg_x as usize] |= self.v_registers[reg_y as usize];
+            Instruction::Move { x, y } => {
+                self.machine.v_registers[x as usize] = self.machine.v_registers[y as usize];
+            }
+            Instruction::Or { x, y } => {
+                self.machine.v_registers[x as usize] |= self.machine.v_registers[y as usize];
 
                 if self.quirks.contains(Quirks::VF_RESET) {
                     // quirk: reset the vF register to 0 on OR
-                    self.v_registers[0xF] = 0;
+                    self.machine.v_registers[0xF] = 0;
                 }
             }
-            (8, reg_x, reg_y, 2) => {
-                log::trace!("AND V{:X}, V{:X}", reg_x, reg_y);
-                self.v_registers[reg_x as usize] &= self.v_registers[reg_y as usize];
+            Instruction::And { x, y } => {
+                self.machine.v_registers[x as usize] &= self.machine.v_registers[y as usize];
 
                 if self.quirks.contains(Quirks::VF_RESET) {
                     // quirk: reset the vF register to 0 on AND
-                    self.v_registers[0xF] = 0;
+                    self.machine.v_registers[0xF] = 0;
                 }
             }
-            (8, reg_x, reg_y, 3) => {
-                log::trace!("XOR V{:X}, V{:X}", reg_x, reg_y);
-                self.v_registers[reg_x as usize] ^= self.v_registers[reg_y as usize];
+            Instruction::Xor { x, y } => {
+                self.machine.v_registers[x as usize] ^= self.machine.v_registers[y as usize];
 
                 if self.quirks.contains(Quirks::VF_RESET) {
                     // quirk: reset the vF register to 0 on XOR
-                    self.v_registers[0xF] = 0;
+                    self.machine.v_registers[0xF] = 0;
                 }
             }
-            (8, reg_x, reg_y, 4) => {
-                log::trace!("ADD V{:X}, V{:X}", reg_x, reg_y);
-                let vx = self.v_registers[reg_x as usize];
-                let vy = self.v_registers[reg_y as usize];
+            Instruction::AddRegisters { x, y } => {
+                let vx = self.machine.v_registers[x as usize];
+                let vy = self.machine.v_registers[y as usize];
 
-                self.v_registers[reg_x as usize] = vx.wrapping_add(vy);
-                self.v_registers[0xF] = vx.checked_add(vy).is_none().into();
+                self.machine.v_registers[x as usize] = vx.wrapping_add(vy);
+                self.machine.v_registers[0xF] = vx.checked_add(vy).is_none().into();
             }
-            (8, reg_x, reg_y, 5) => {
-                log::trace!("SUB V{:X}, V{:X}", reg_x, reg_y);
-                let vx = self.v_registers[reg_x as usize];
-                let vy = self.v_registers[reg_y as usize];
+            Instruction::Sub { x, y } => {
+                let vx = self.machine.v_registers[x as usize];
+                let vy = self.machine.v_registers[y as usize];
 
                 let (value, overflow) = vx.overflowing_sub(vy);
 
-                self.v_registers[reg_x as usize] = value;
-                self.v_registers[0xF] = (!overflow).into();
+                self.machine.v_registers[x as usize] = value;
+                self.machine.v_registers[0xF] = (!overflow).into();
             }
-            (8, reg_x, reg_y, 6) => {
-                log::trace!("SHR V{:X}", reg_x);
-
+            Instruction::ShiftRight { x, y } => {
                 if self.quirks.contains(Quirks::SHIFTING) {
-                    let lsb = self.v_registers[reg_x as usize] & 1;
+                    let lsb = self.machine.v_registers[x as usize] & 1;
                     // shifting quirk: only modifies vX
-                    self.v_registers[reg_x as usize] >>= 1;
+                    self.machine.v_registers[x as usize] >>= 1;
                     // overflow register gets the least significant bit since it's the one chopped off
-                    self.v_registers[0xF] = lsb;
+                    self.machine.v_registers[0xF] = lsb;
                 } else {
-                    let lsb = self.v_registers[reg_y as usize] & 1;
+                    let lsb = self.machine.v_registers[y as usize] & 1;
                     // normal behavior: sets vX to vY and then shifts vX
-                    self.v_registers[reg_x as usize] = self.v_registers[reg_y as usize] >> 1;
+                    self.machine.v_registers[x as usize] =
+                        self.machine.v_registers[y as usize] >> 1;
                     // overflow register gets the least significant bit since it's the one chopped off
-                    self.v_registers[0xF] = lsb;
+                    self.machine.v_registers[0xF] = lsb;
                 }
             }
-            (8, reg_x, reg_y, 7) => {
-                log::trace!("SUBN V{:X}, V{:X}", reg_x, reg_y);
-                let vx = self.v_registers[reg_x as usize];
-                let vy = self.v_registers[reg_y as usize];
+            Instruction::SubReversed { x, y } => {
+                let vx = self.machine.v_registers[x as usize];
+                let vy = self.machine.v_registers[y as usize];
 
                 let (new_value, overflow) = vy.overflowing_sub(vx);
 
-                self.v_registers[reg_x as usize] = new_value;
-                self.v_registers[0xF] = (!overflow).into();
+                self.machine.v_registers[x as usize] = new_value;
+                self.machine.v_registers[0xF] = (!overflow).into();
             }
-            (8, reg_x, reg_y, 0xE) => {
-                log::trace!("SHL V{:X}", reg_x);
-
+            Instruction::ShiftLeft { x, y } => {
                 if self.quirks.contains(Quirks::SHIFTING) {
-                    let lsb = (self.v_registers[reg_x as usize] >> 7) & 1;
+                    let msb = (self.machine.v_registers[x as usize] >> 7) & 1;
                     // shifting quirk: only modifies vX
-                    self.v_registers[reg_x as usize] <<= 1;
+                    self.machine.v_registers[x as usize] <<= 1;
                     // overflow register gets the most significant bit since it's the one chopped off
-                    self.v_registers[0xF] = lsb;
+                    self.machine.v_registers[0xF] = msb;
                 } else {
-                    let lsb = (self.v_registers[reg_y as usize] >> 7) & 1;
+                    let msb = (self.machine.v_registers[y as usize] >> 7) & 1;
                     // normal behavior: sets vX to vY and then shifts vX
-                    self.v_registers[reg_x as usize] = self.v_registers[reg_y as usize] << 1;
+                    self.machine.v_registers[x as usize] =
+                        self.machine.v_registers[y as usize] << 1;
                     // overflow register gets the most significant bit since it's the one chopped off
-                    self.v_registers[0xF] = lsb;
+                    self.machine.v_registers[0xF] = msb;
                 }
             }
-
-            (9, reg_x, reg_y, 0) => {
-                log::trace!("SNE V{:X}, V{:X}", reg_x, reg_y);
-                if self.v_registers[reg_x as usize] != self.v_registers[reg_y as usize] {
-                    self.program_counter += 2;
+            Instruction::SkipIfRegistersNotEqual { x, y } => {
+                if self.machine.v_registers[x as usize] != self.machine.v_registers[y as usize] {
+                    self.machine.program_counter += 2;
                 }
             }
-
-            (0xA, _, _, _) => {
-                let val = opcode & 0xFFF;
-                log::trace!("LD I, 0x{:04x}", val);
-                self.index_register = val;
+            Instruction::LoadIndex { addr } => {
+                self.machine.index_register = addr;
             }
-
-            (0xB, reg_x, _, _) => {
-                let val = opcode & 0xFFF;
-                log::trace!("JP V0, 0x{:04x}", val);
-
+            Instruction::JumpOffset { addr } => {
                 let reg_value = if self.quirks.contains(Quirks::JUMPING) {
-                    // jumping quirk: adds vX instead of v0
-                    self.v_registers[reg_x as usize]
+                    // jumping quirk: adds vX instead of v0, where X is the highest nibble of the
+                    // address
+                    self.machine.v_registers[(addr >> 8) as usize]
                 } else {
                     // normal behavior: adds v0
-                    self.v_registers[0]
+                    self.machine.v_registers[0]
                 };
 
-                self.program_counter = reg_value as u16 + val;
+                self.machine.program_counter = reg_value as u16 + addr;
             }
-
-            (0xC, reg_x, _, _) => {
-                let val = (opcode & 0xFF) as u8;
-                let random_byte = rand::random::<u8>();
-                log::trace!("RND V{:X}, {}", reg_x, val);
-                self.v_registers[reg_x as usize] = random_byte & val;
+            Instruction::Random { x, value } => {
+                let random_byte = self.machine.rng.next_u8();
+                self.machine.v_registers[x as usize] = random_byte & value;
             }
+            Instruction::Draw { x, y, rows } => {
+                let x_coord = self.machine.v_registers[x as usize];
+                let y_coord = self.machine.v_registers[y as usize];
+                log::trace!("Drawing {} rows at ({}, {})", rows, x_coord, y_coord);
 
-            (0xD, reg_x, reg_y, n) => {
-                let x_coord = self.v_registers[reg_x as usize];
-                let y_coord = self.v_registers[reg_y as usize];
-                log::trace!(
-                    "DRW V{:X}, V{:X}, {} (draw {} rows at ({}, {}))",
-                    reg_x, reg_y, n, n, x_coord, y_coord
-                );
-
-                let sprite_addr = self.index_register as usize;
-                let num_rows = n as usize;
-                let sprite = &self.memory[sprite_addr..sprite_addr + num_rows];
+                let sprite_addr = self.machine.index_register as usize;
+                let num_rows = rows as usize;
+                // sprites running past the end of memory wrap around to the start
+                let mut sprite = [0; 16];
+                for (row, byte) in sprite.iter_mut().enumerate().take(num_rows) {
+                    *byte = self.read_memory(sprite_addr + row);
+                }
 
-                let collision = self
-                    .window
-                    .draw_sprite(x_coord as usize, y_coord as usize, num_rows, sprite, self.quirks.contains(Quirks::CLIPPING));
+                let collision = self.machine.window.draw_sprite(
+                    x_coord as usize,
+                    y_coord as usize,
+                    num_rows,
+                    &sprite,
+                    self.quirks.contains(Quirks::CLIPPING),
+                );
 
-                self.v_registers[0xF] = collision.into();
+                self.machine.v_registers[0xF] = collision.into();
 
                 if self.quirks.contains(Quirks::DISPLAY_WAIT) {
                     log::trace!("Display wait quirk; waiting for next VBlank");
-                    self.waiting_for_vblank = true;
+                    self.machine.waiting_for_vblank = true;
                 }
             }
-
-            (0xE, reg_x, 9, 0xE) => {
-                log::trace!("SKP V{:X}", reg_x);
-                if self.keys[self.v_registers[reg_x as usize] as usize] {
-                    self.program_counter += 2;
+            // only the low nibble of vX selects a key
+            Instruction::SkipIfKey { x } => {
+                if self.machine.keys[(self.machine.v_registers[x as usize] & 0xF) as usize] {
+                    self.machine.program_counter += 2;
                 }
             }
-
-            (0xE, reg_x, 0xA, 1) => {
-                log::trace!("SKNP V{:X}", reg_x);
-                if !self.keys[self.v_registers[reg_x as usize] as usize] {
-                    self.program_counter += 2;
+            Instruction::SkipIfNotKey { x } => {
+                if !self.machine.keys[(self.machine.v_registers[x as usize] & 0xF) as usize] {
+                    self.machine.program_counter += 2;
                 }
             }
-
-            (0xF, reg_x, 0, 7) => {
-                log::trace!("LD V{:X}, DT", reg_x);
-                self.v_registers[reg_x as usize] = self.delay_timer;
+            Instruction::LoadDelayTimer { x } => {
+                self.machine.v_registers[x as usize] = self.machine.delay_timer;
             }
-
-            (0xF, reg_x, 0, 0xA) => {
-                log::trace!("LD V{:X}, K (waiting for key)", reg_x);
-                self.key_wait_register = Some(reg_x as u8);
+            Instruction::WaitForKey { x } => {
+                log::trace!("Waiting for key");
+                self.machine.key_wait_register = Some(x);
             }
-
-            (0xF, reg_x, 1, 5) => {
-                log::trace!("LD DT, V{:X}", reg_x);
-                self.delay_timer = self.v_registers[reg_x as usize];
+            Instruction::SetDelayTimer { x } => {
+                self.machine.delay_timer = self.machine.v_registers[x as usize];
             }
-
-            (0xF, reg_x, 1, 8) => {
-                log::trace!("LD ST, V{:X}", reg_x);
-                self.sound_timer = self.v_registers[reg_x as usize];
+            Instruction::SetSoundTimer { x } => {
+                self.machine.sound_timer = self.machine.v_registers[x as usize];
             }
-
-            (0xF, reg_x, 1, 0xE) => {
-                log::trace!("ADD I, V{:X}", reg_x);
-                self.index_register += self.v_registers[reg_x as usize] as u16;
+            Instruction::AddIndex { x } => {
+                self.machine.index_register = self
+                    .machine
+                    .index_register
+                    .wrapping_add(self.machine.v_registers[x as usize] as u16);
             }
-
-            (0xF, reg_x, 2, 9) => {
-                log::trace!("LD F, V{:X}", reg_x);
-                self.index_register = self.v_registers[reg_x as usize] as u16 * 5;
+            Instruction::LoadFont { x } => {
+                self.machine.index_register = self.machine.v_registers[x as usize] as u16 * 5;
             }
+            Instruction::StoreDecimal { x } => {
+                let vx = self.machine.v_registers[x as usize];
+                let i = self.machine.index_register as usize;
 
-            (0xF, reg_x, 3, 3) => {
-                log::trace!("LD B, V{:X}", reg_x);
-                let vx = self.v_registers[reg_x as usize];
-                let i = self.index_register as usize;
-
-                self.memory[i] = vx / 100;
-                self.memory[i + 1] = (vx / 10) % 10;
-                self.memory[i + 2] = vx % 10;
+                self.write_memory(i, vx / 100);
+                self.write_memory(i + 1, (vx / 10) % 10);
+                self.write_memory(i + 2, vx % 10);
             }
-
-            (0xF, reg_x, 5, 5) => {
-                log::trace!("LD [I], V{:X}", reg_x);
-                for reg in 0..=reg_x {
-                    let addr = (self.index_register + reg) as usize;
-                    self.memory[addr] = self.v_registers[reg as usize];
+            Instruction::StoreRegisters { x } => {
+                for reg in 0..=x as usize {
+                    let addr = self.machine.index_register as usize + reg;
+                    self.write_memory(addr, self.machine.v_registers[reg]);
                 }
 
                 if self.quirks.contains(Quirks::MEMORY) {
                     // quirk: save and load opcodes increment the index register
-                    self.index_register += reg_x + 1;
+                    self.machine.index_register =
+                        self.machine.index_register.wrapping_add(x as u16 + 1);
                 }
             }
-
-            (0xF, reg_x, 6, 5) => {
-                log::trace!("LD V{:X}, [I]", reg_x);
-                for reg in 0..=reg_x {
-                    let addr = (self.index_register + reg) as usize;
-                    self.v_registers[reg as usize] = self.memory[addr];
+            Instruction::LoadRegisters { x } => {
+                for reg in 0..=x as usize {
+                    let addr = self.machine.index_register as usize + reg;
+                    self.machine.v_registers[reg] = self.read_memory(addr);
                 }
 
                 if self.quirks.contains(Quirks::MEMORY) {
                     // quirk: save and load opcodes increment the index register
-                    self.index_register += reg_x + 1;
+                    self.machine.index_register =
+                        self.machine.index_register.wrapping_add(x as u16 + 1);
                 }
             }
-
-            (_, _, _, _) => log::error!("Unimplemented opcode: 0x{:04x}", opcode),
+            Instruction::Unknown(opcode) => log::error!("Unimplemented opcode: 0x{:04x}", opcode),
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,375 @@
+//! Property tests comparing single instructions against a reference model.
+//!
+//! The model decodes opcodes itself and follows the specification as plainly as possible, so
+//! that the decoder and interpreter can be refactored freely as long as they still agree with
+//! it.
+
+use proptest::prelude::*;
+
+use super::*;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// The observable state of the machine
+#[derive(Debug, Clone, PartialEq)]
+struct Machine {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    memory: Vec<u8>,
+    /// Return addresses, from the bottom of the stack
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    keys: [bool; 16],
+    /// Row by row
+    display: Vec<bool>,
+    waiting_for_vblank: bool,
+    key_wait: Option<u8>,
+}
+
+impl Machine {
+    /// Sets up an emulator in this state. With the decode cache, the instruction at the program
+    /// counter is run once and the state restored, so that it runs from the cache next time.
+    fn to_emulator(&self, quirks: Quirks, decode_cache: bool) -> Chip8 {
+        let mut emulator = Chip8::new().override_quirks(quirks);
+        emulator.machine.v_registers = self.v;
+        emulator.machine.index_register = self.i;
+        emulator.machine.program_counter = self.pc;
+        emulator.machine.memory[0..MEMORY_SIZE].copy_from_slice(&self.memory);
+        for &addr in &self.stack {
+            let _ = emulator.machine.stack.push(addr);
+        }
+        emulator.machine.delay_timer = self.delay;
+        emulator.machine.sound_timer = self.sound;
+        emulator.machine.keys = self.keys;
+        for (index, &pixel) in self.display.iter().enumerate() {
+            emulator
+                .machine
+                .window
+                .set_pixel(index % WIDTH, index / WIDTH, pixel);
+        }
+
+        if decode_cache {
+            emulator = emulator.with_decode_cache();
+            let machine = emulator.machine.clone();
+            emulator.tick_cpu();
+            emulator.set_machine(machine);
+        }
+        emulator
+    }
+
+    /// Reads the state back out of an emulator
+    fn from_emulator(emulator: &mut Chip8) -> Self {
+        let mut stack = Vec::new();
+        while let Ok(addr) = emulator.machine.stack.pop() {
+            stack.insert(0, addr);
+        }
+
+        Self {
+            v: emulator.machine.v_registers,
+            i: emulator.machine.index_register,
+            pc: emulator.machine.program_counter,
+            memory: emulator.machine.memory.as_slice().to_vec(),
+            stack,
+            delay: emulator.machine.delay_timer,
+            sound: emulator.machine.sound_timer,
+            keys: emulator.machine.keys,
+            display: (0..WIDTH * HEIGHT)
+                .map(|index| {
+                    emulator
+                        .machine
+                        .window
+                        .get_pixel(index % WIDTH, index / WIDTH)
+                })
+                .collect(),
+            waiting_for_vblank: emulator.machine.waiting_for_vblank,
+            key_wait: emulator.machine.key_wait_register,
+        }
+    }
+
+    fn read(&self, addr: usize) -> u8 {
+        self.memory[addr % MEMORY_SIZE]
+    }
+
+    fn write(&mut self, addr: usize, value: u8) {
+        self.memory[addr % MEMORY_SIZE] = value;
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc += 2;
+        }
+    }
+
+    /// Runs the opcode at the program counter as the specification describes. The random
+    /// number for `Cxkk` is passed in, since it can't be predicted.
+    fn step(&mut self, quirks: Quirks, random: u8) {
+        let opcode =
+            (self.read(self.pc as usize) as u16) << 8 | self.read(self.pc as usize + 1) as u16;
+        self.pc = self.pc % MEMORY_SIZE as u16 + 2;
+
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let kk = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let (vx, vy) = (self.v[x], self.v[y]);
+
+        match opcode >> 12 {
+            0x0 if opcode == 0x00E0 => self.display.fill(false),
+            0x0 if opcode == 0x00EE => {
+                if let Some(addr) = self.stack.pop() {
+                    self.pc = addr;
+                }
+            }
+            0x0 => (),
+            0x1 => self.pc = nnn,
+            0x2 if self.stack.len() < 16 => {
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            }
+            0x3 => self.skip_if(vx == kk),
+            0x4 => self.skip_if(vx != kk),
+            0x5 if n == 0 => self.skip_if(vx == vy),
+            0x6 => self.v[x] = kk,
+            0x7 => self.v[x] = vx.wrapping_add(kk),
+            // the result is always written before the flag, so the flag wins if x is F
+            0x8 => match n {
+                0x0 => self.v[x] = vy,
+                0x1..=0x3 => {
+                    self.v[x] = match n {
+                        0x1 => vx | vy,
+                        0x2 => vx & vy,
+                        _ => vx ^ vy,
+                    };
+                    if quirks.contains(Quirks::VF_RESET) {
+                        self.v[0xF] = 0;
+                    }
+                }
+                0x4 => {
+                    let sum = vx as u16 + vy as u16;
+                    self.v[x] = sum as u8;
+                    self.v[0xF] = (sum > 0xFF) as u8;
+                }
+                0x5 => {
+                    self.v[x] = vx.wrapping_sub(vy);
+                    self.v[0xF] = (vx >= vy) as u8;
+                }
+                0x6 => {
+                    let source = if quirks.contains(Quirks::SHIFTING) {
+                        vx
+                    } else {
+                        vy
+                    };
+                    self.v[x] = source >> 1;
+                    self.v[0xF] = source & 1;
+                }
+                0x7 => {
+                    self.v[x] = vy.wrapping_sub(vx);
+                    self.v[0xF] = (vy >= vx) as u8;
+                }
+                0xE => {
+                    let source = if quirks.contains(Quirks::SHIFTING) {
+                        vx
+                    } else {
+                        vy
+                    };
+                    self.v[x] = source << 1;
+                    self.v[0xF] = source >> 7;
+                }
+                _ => (),
+            },
+            0x9 if n == 0 => self.skip_if(vx != vy),
+            0xA => self.i = nnn,
+            0xB => {
+                let offset = if quirks.contains(Quirks::JUMPING) {
+                    vx
+                } else {
+                    self.v[0]
+                };
+                self.pc = nnn + offset as u16;
+            }
+            0xC => self.v[x] = random & kk,
+            0xD => self.draw(quirks, vx as usize, vy as usize, n as usize),
+            0xE if kk == 0x9E => self.skip_if(self.keys[(vx & 0xF) as usize]),
+            0xE if kk == 0xA1 => self.skip_if(!self.keys[(vx & 0xF) as usize]),
+            0xF => match kk {
+                0x07 => self.v[x] = self.delay,
+                0x0A => self.key_wait = Some(x as u8),
+                0x15 => self.delay = vx,
+                0x18 => self.sound = vx,
+                0x1E => self.i = self.i.wrapping_add(vx as u16),
+                0x29 => self.i = vx as u16 * 5,
+                0x33 => {
+                    self.write(self.i as usize, vx / 100);
+                    self.write(self.i as usize + 1, vx / 10 % 10);
+                    self.write(self.i as usize + 2, vx % 10);
+                }
+                0x55 | 0x65 => {
+                    for reg in 0..=x {
+                        let addr = self.i as usize + reg;
+                        if kk == 0x55 {
+                            self.write(addr, self.v[reg]);
+                        } else {
+                            self.v[reg] = self.read(addr);
+                        }
+                    }
+                    if quirks.contains(Quirks::MEMORY) {
+                        self.i = self.i.wrapping_add(x as u16 + 1);
+                    }
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    /// Draws a sprite one pixel at a time
+    fn draw(&mut self, quirks: Quirks, x: usize, y: usize, rows: usize) {
+        let clipping = quirks.contains(Quirks::CLIPPING);
+        let mut collision = false;
+
+        for row in 0..rows {
+            let byte = self.read(self.i as usize + row);
+            let py = y % HEIGHT + row;
+            if clipping && py >= HEIGHT {
+                continue;
+            }
+
+            for column in 0..8 {
+                let px = x % WIDTH + column;
+                if byte & (0x80 >> column) == 0 || (clipping && px >= WIDTH) {
+                    continue;
+                }
+
+                let pixel = &mut self.display[py % HEIGHT * WIDTH + px % WIDTH];
+                collision |= *pixel;
+                *pixel = !*pixel;
+            }
+        }
+
+        self.v[0xF] = collision as u8;
+        if quirks.contains(Quirks::DISPLAY_WAIT) {
+            self.waiting_for_vblank = true;
+        }
+    }
+}
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    (0..=Quirks::all().bits()).prop_map(Quirks::from_bits_truncate)
+}
+
+/// A machine in a random state about to run the given opcode
+fn machine(opcode: impl Strategy<Value = u16>) -> impl Strategy<Value = Machine> {
+    (
+        (
+            any::<[u8; 16]>(),
+            any::<u16>(),
+            0..MEMORY_SIZE as u16,
+            opcode,
+        ),
+        proptest::collection::vec(any::<u8>(), MEMORY_SIZE),
+        proptest::collection::vec(any::<u16>(), 0..=16),
+        (any::<u8>(), any::<u8>(), any::<[bool; 16]>()),
+        proptest::collection::vec(any::<bool>(), WIDTH * HEIGHT),
+    )
+        .prop_map(
+            |((v, i, pc, opcode), mut memory, stack, (delay, sound, keys), display)| {
+                let [high, low] = opcode.to_be_bytes();
+                memory[pc as usize] = high;
+                memory[(pc as usize + 1) % MEMORY_SIZE] = low;
+                Machine {
+                    v,
+                    i,
+                    pc,
+                    memory,
+                    stack,
+                    delay,
+                    sound,
+                    keys,
+                    display,
+                    waiting_for_vblank: false,
+                    key_wait: None,
+                }
+            },
+        )
+}
+
+/// Opcodes of the form `_xyn` with the given top nibble, for any `x` and `y`
+fn with_registers(top: u16, n: impl Strategy<Value = u16>) -> impl Strategy<Value = u16> {
+    (0..16u16, 0..16u16, n).prop_map(move |(x, y, n)| top << 12 | x << 8 | y << 4 | n)
+}
+
+/// Runs one instruction on the emulator, with and without the decode cache, and on the model
+/// and compares the results
+fn check(machine: Machine, quirks: Quirks) -> Result<(), TestCaseError> {
+    for decode_cache in [false, true] {
+        check_with(machine.clone(), quirks, decode_cache)?;
+    }
+    Ok(())
+}
+
+fn check_with(machine: Machine, quirks: Quirks, decode_cache: bool) -> Result<(), TestCaseError> {
+    let mut emulator = machine.to_emulator(quirks, decode_cache);
+    let instruction = emulator.tick_cpu();
+    prop_assert!(instruction.is_some());
+
+    let actual = Machine::from_emulator(&mut emulator);
+    let mut expected = machine;
+    // Cxkk is random, so the model takes whatever the emulator generated if it's possible
+    let random = match instruction {
+        Some(Instruction::Random { x, value }) => {
+            let generated = actual.v[x as usize];
+            prop_assert_eq!(generated & !value, 0);
+            generated
+        }
+        _ => 0,
+    };
+    expected.step(quirks, random);
+
+    prop_assert_eq!(
+        actual,
+        expected,
+        "{:?} with {:?}, decode cache {}",
+        instruction,
+        quirks,
+        decode_cache
+    );
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn arithmetic_matches_model(
+        machine in machine(with_registers(0x8, prop_oneof![0..8u16, Just(0xE)])),
+        quirks in quirks(),
+    ) {
+        check(machine, quirks)?;
+    }
+
+    #[test]
+    fn memory_matches_model(
+        machine in machine(with_registers(0xF, Just(0)).prop_flat_map(|opcode| {
+            prop_oneof![Just(0x1E), Just(0x29), Just(0x33), Just(0x55), Just(0x65)]
+                .prop_map(move |kk| opcode & 0xFF00 | kk)
+        })),
+        quirks in quirks(),
+    ) {
+        check(machine, quirks)?;
+    }
+
+    #[test]
+    fn drawing_matches_model(
+        machine in machine(with_registers(0xD, 0..16u16)),
+        quirks in quirks(),
+    ) {
+        check(machine, quirks)?;
+    }
+
+    #[test]
+    fn any_opcode_matches_model(machine in machine(any::<u16>()), quirks in quirks()) {
+        check(machine, quirks)?;
+    }
+}
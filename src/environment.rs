@@ -0,0 +1,182 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::{
+    emulator::{Chip8, Quirks},
+    expression::Expression,
+    virtual_buffer::VirtualDisplay,
+};
+
+/// A Gym-style reinforcement learning environment that plays a ROM.
+///
+/// Each [`Self::step`] holds down a set of keys for a number of frames and returns the display,
+/// the reward, and whether the episode is over. The reward and end of the episode are read from
+/// the machine's registers and memory with [`Expression`]s, such as
+/// `mem[0x2F0] - prev(mem[0x2F0])` for the change in a score stored at `0x2F0`. `prev(...)` is
+/// the value at the start of the step.
+///
+/// Actions are a bitmask of the keys to hold, where bit `n` holds key `n`.
+///
+/// # Example
+/// ```
+/// # use chip8_rs::environment::Environment;
+/// # fn main() -> Result<(), String> {
+/// // counts up in v0
+/// let rom = vec![0x70, 0x01, 0x12, 0x00];
+/// let mut env = Environment::new(rom)
+///     .with_reward("v0 - prev(v0)".parse()?)
+///     .with_done("v0 >= 100".parse()?)
+///     .with_frame_skip(4);
+///
+/// let observation = env.reset();
+/// let (observation, reward, done) = env.step(0b0000_0000_0001_0000);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Environment {
+    emulator: Chip8,
+    /// The program, loaded again on every reset
+    rom: Vec<u8>,
+    /// The reward for each step, or always 0 if not set
+    reward: Option<Expression>,
+    /// Whether the episode is over after each frame, or never if not set
+    done: Option<Expression>,
+    /// The number of frames each action is held for
+    frame_skip: u32,
+    /// The chance of repeating the previous frame's keys instead of the new action, each frame
+    sticky_actions: f64,
+    /// Decides when actions stick
+    rng: StdRng,
+    /// The keys currently held down
+    held: u16,
+}
+
+impl Environment {
+    /// Creates an environment that plays the given program, with a frame skip of 1, no sticky
+    /// actions, no reward, and episodes that never end
+    pub fn new(rom: Vec<u8>) -> Self {
+        let mut env = Self {
+            emulator: Chip8::new(),
+            rom,
+            reward: None,
+            done: None,
+            frame_skip: 1,
+            sticky_actions: 0.0,
+            rng: StdRng::from_os_rng(),
+            held: 0,
+        };
+        env.reset();
+        env
+    }
+
+    /// Overrides the default quirks
+    pub fn with_quirks(mut self, quirks: Quirks) -> Self {
+        self.emulator = self.emulator.override_quirks(quirks);
+        self
+    }
+
+    /// Computes the reward for each step with an expression, evaluated once the step's frames
+    /// have run
+    pub fn with_reward(mut self, reward: Expression) -> Self {
+        self.reward = Some(reward);
+        self
+    }
+
+    /// Ends the episode once an expression is non-zero, checked after every frame
+    pub fn with_done(mut self, done: Expression) -> Self {
+        self.done = Some(done);
+        self
+    }
+
+    /// Holds each action for this many frames. The step ends early if the episode does.
+    pub fn with_frame_skip(mut self, frames: u32) -> Self {
+        self.frame_skip = frames.max(1);
+        self
+    }
+
+    /// Each frame, keeps holding the previous frame's keys instead of the new action with the
+    /// given probability, from 0.0 to 1.0. This makes it harder for an agent to memorize a
+    /// sequence of actions. Fails if the probability isn't between 0.0 and 1.0.
+    pub fn with_sticky_actions(mut self, probability: f64) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(format!(
+                "Sticky action probability {} isn't between 0 and 1",
+                probability
+            ));
+        }
+        self.sticky_actions = probability;
+        Ok(self)
+    }
+
+    /// Seeds the random number generator used for sticky actions and for seeding the emulator's
+    /// own generator on every reset, so that runs are reproducible. Without a seed, each
+    /// environment makes different random choices.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.emulator.seed_rng(self.rng.random());
+        self
+    }
+
+    /// The emulator, for reading anything not in the observation
+    pub fn emulator(&self) -> &Chip8 {
+        &self.emulator
+    }
+
+    /// Starts a new episode, returning the first observation
+    pub fn reset(&mut self) -> VirtualDisplay {
+        self.emulator.reset();
+        self.emulator.seed_rng(self.rng.random());
+        self.emulator.load(&self.rom);
+        self.held = 0;
+        self.emulator.window().clone()
+    }
+
+    /// Holds down the keys in `action` for the frame skip, returning the display, the reward,
+    /// and whether the episode is over
+    pub fn step(&mut self, action: u16) -> (VirtualDisplay, f64, bool) {
+        let captured = self
+            .reward
+            .as_ref()
+            .map(|reward| reward.capture(&self.emulator))
+            .unwrap_or_default();
+        let done_captured = self
+            .done
+            .as_ref()
+            .map(|done| done.capture(&self.emulator))
+            .unwrap_or_default();
+
+        let mut done = false;
+        for _ in 0..self.frame_skip {
+            if !self.rng.random_bool(self.sticky_actions) {
+                self.hold_keys(action);
+            }
+            self.emulator.run_frame();
+
+            done = self
+                .done
+                .as_ref()
+                .is_some_and(|expression| expression.evaluate(&self.emulator, &done_captured) != 0);
+            if done {
+                break;
+            }
+        }
+
+        let reward = self.reward.as_ref().map_or(0.0, |reward| {
+            reward.evaluate(&self.emulator, &captured) as f64
+        });
+        (self.emulator.window().clone(), reward, done)
+    }
+
+    /// Presses and releases keys so that exactly the keys in `keys` are held
+    fn hold_keys(&mut self, keys: u16) {
+        let changed = self.held ^ keys;
+        for key in (0..16).filter(|key| changed & (1 << key) != 0) {
+            if keys & (1 << key) != 0 {
+                self.emulator.press_key(key);
+            } else {
+                self.emulator.release_key(key);
+            }
+        }
+        self.held = keys;
+    }
+}
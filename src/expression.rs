@@ -0,0 +1,373 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{emulator::Chip8, memory::MEMORY_SIZE};
+
+/// An integer expression over the state of the machine, used to compute rewards and detect the
+/// end of an episode in an [`crate::environment::Environment`].
+///
+/// Expressions are written like `mem[0x2F0] - prev(mem[0x2F0])` and may use:
+/// * Decimal and `0x` hex literals
+/// * `v0` to `vF`, `i`, `pc`, `dt` (delay timer), and `st` (sound timer)
+/// * `mem[addr]`, the byte at `addr`. Addresses past the end of memory wrap around.
+/// * `prev(expr)`, the value of `expr` when it was last captured with [`Self::capture`], such as
+///   at the start of a step
+/// * The operators `* / % << >> &`, then `+ - | ^`, then `== != < <= > >=`, then `&&`, then
+///   `||`, from tightest to loosest binding, along with unary `-` and `!` and parentheses
+///
+/// Arithmetic wraps on overflow, and dividing by zero gives 0. Comparisons and logical
+/// operators give 1 for true and 0 for false.
+///
+/// Expressions can be at most [`MAX_LENGTH`] bytes long, with parentheses, brackets, and unary
+/// operators nested at most [`MAX_DEPTH`] deep, so that parsing and evaluating them can't
+/// overflow the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    /// The text the expression was parsed from
+    source: String,
+    root: Node,
+    /// The operands of every `prev(...)`, indexed by [`Node::Prev`]
+    captured: Vec<Node>,
+}
+
+/// The longest expression that can be parsed, in bytes
+pub const MAX_LENGTH: usize = 1024;
+/// How deeply parentheses, brackets, and unary operators can be nested
+pub const MAX_DEPTH: usize = 64;
+
+/// A node of a parsed expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Literal(i64),
+    Register(u8),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+    Memory(Box<Node>),
+    /// The captured value of [`Expression::captured`] at this index
+    Prev(usize),
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    BitAnd,
+    Add,
+    Sub,
+    BitOr,
+    BitXor,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// The operators at each precedence level, from loosest to tightest binding
+    const PRECEDENCE: [&[(&str, BinaryOp)]; 5] = [
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ],
+        &[
+            ("+", BinaryOp::Add),
+            ("-", BinaryOp::Sub),
+            ("|", BinaryOp::BitOr),
+            ("^", BinaryOp::BitXor),
+        ],
+        &[
+            ("*", BinaryOp::Mul),
+            ("/", BinaryOp::Div),
+            ("%", BinaryOp::Rem),
+            ("<<", BinaryOp::Shl),
+            (">>", BinaryOp::Shr),
+            ("&", BinaryOp::BitAnd),
+        ],
+    ];
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            Self::Mul => a.wrapping_mul(b),
+            Self::Div => a.checked_div(b).unwrap_or(0),
+            Self::Rem => a.checked_rem(b).unwrap_or(0),
+            Self::Shl => u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shl(b))
+                .unwrap_or(0),
+            Self::Shr => u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shr(b))
+                .unwrap_or(0),
+            Self::BitAnd => a & b,
+            Self::Add => a.wrapping_add(b),
+            Self::Sub => a.wrapping_sub(b),
+            Self::BitOr => a | b,
+            Self::BitXor => a ^ b,
+            Self::Eq => (a == b).into(),
+            Self::Ne => (a != b).into(),
+            Self::Lt => (a < b).into(),
+            Self::Le => (a <= b).into(),
+            Self::Gt => (a > b).into(),
+            Self::Ge => (a >= b).into(),
+            Self::And => (a != 0 && b != 0).into(),
+            Self::Or => (a != 0 || b != 0).into(),
+        }
+    }
+}
+
+impl Expression {
+    /// Evaluates the operands of every `prev(...)` against the current state, returning the
+    /// values to pass to [`Self::evaluate`] later
+    pub fn capture(&self, emulator: &Chip8) -> Vec<i64> {
+        self.captured
+            .iter()
+            .map(|node| node.evaluate(emulator, &[]))
+            .collect()
+    }
+
+    /// Evaluates the expression against the current state, using values from [`Self::capture`]
+    /// for `prev(...)`
+    pub fn evaluate(&self, emulator: &Chip8, captured: &[i64]) -> i64 {
+        self.root.evaluate(emulator, captured)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, emulator: &Chip8, captured: &[i64]) -> i64 {
+        match self {
+            Self::Literal(value) => *value,
+            Self::Register(x) => emulator.registers()[*x as usize].into(),
+            Self::Index => emulator.index_register().into(),
+            Self::ProgramCounter => emulator.program_counter().into(),
+            Self::DelayTimer => emulator.delay_timer().into(),
+            Self::SoundTimer => emulator.sound_timer().into(),
+            Self::Memory(addr) => {
+                let addr = addr
+                    .evaluate(emulator, captured)
+                    .rem_euclid(MEMORY_SIZE as i64);
+                emulator.memory()[addr as usize].into()
+            }
+            Self::Prev(index) => captured.get(*index).copied().unwrap_or(0),
+            Self::Negate(node) => node.evaluate(emulator, captured).wrapping_neg(),
+            Self::Not(node) => (node.evaluate(emulator, captured) == 0).into(),
+            Self::Binary(op, a, b) => op.apply(
+                a.evaluate(emulator, captured),
+                b.evaluate(emulator, captured),
+            ),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > MAX_LENGTH {
+            return Err(format!(
+                "Expressions can't be longer than {} bytes, got {}",
+                MAX_LENGTH,
+                s.len()
+            ));
+        }
+
+        let mut parser = Parser {
+            source: s,
+            rest: s,
+            captured: Vec::new(),
+            in_prev: false,
+            depth: 0,
+        };
+        let root = parser.parse_binary(0)?;
+        parser.skip_whitespace();
+        if !parser.rest.is_empty() {
+            return Err(parser.error("Unexpected input"));
+        }
+
+        Ok(Self {
+            source: s.to_string(),
+            root,
+            captured: parser.captured,
+        })
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A recursive descent parser for [`Expression`]s
+struct Parser<'a> {
+    /// The whole expression, for error messages
+    source: &'a str,
+    /// The input that hasn't been parsed yet
+    rest: &'a str,
+    /// The operands of every `prev(...)` parsed so far
+    captured: Vec<Node>,
+    /// Whether the parser is inside a `prev(...)`, which can't be nested
+    in_prev: bool,
+    /// How many parentheses, brackets, and unary operators the parser is inside
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let position = self.source.len() - self.rest.len();
+        format!("{} at position {} in `{}`", message, position, self.source)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// Consumes `token` if the input starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", token)))
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing instead if that's deeper than [`MAX_DEPTH`]
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error(&format!("Nested more than {} deep", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses a chain of binary operators at the given index of [`BinaryOp::PRECEDENCE`] or
+    /// tighter
+    fn parse_binary(&mut self, level: usize) -> Result<Node, String> {
+        let Some(operators) = BinaryOp::PRECEDENCE.get(level) else {
+            return self.parse_unary();
+        };
+
+        let mut node = self.parse_binary(level + 1)?;
+        'chain: loop {
+            for &(token, op) in operators.iter() {
+                // don't mistake `&&` for `&`, or `||` for `|`
+                let doubled = matches!(token, "&" | "|")
+                    && self.rest.trim_start().starts_with(&token.repeat(2));
+                if !doubled && self.eat(token) {
+                    let rhs = self.parse_binary(level + 1)?;
+                    node = Node::Binary(op, Box::new(node), Box::new(rhs));
+                    continue 'chain;
+                }
+            }
+            return Ok(node);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Negate(Box::new(self.nested(Self::parse_unary)?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::Not(Box::new(self.nested(Self::parse_unary)?)));
+        }
+        self.parse_operand()
+    }
+
+    fn parse_operand(&mut self) -> Result<Node, String> {
+        if self.eat("(") {
+            let node = self.nested(|parser| parser.parse_binary(0))?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+
+        self.skip_whitespace();
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(len);
+        if word.is_empty() {
+            return Err(self.error("Expected a value"));
+        }
+
+        let lower = word.to_ascii_lowercase();
+        let node = if let Some(hex) = lower.strip_prefix("0x") {
+            Node::Literal(
+                i64::from_str_radix(hex, 16)
+                    .map_err(|_| self.error(&format!("Invalid number `{}`", word)))?,
+            )
+        } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+            Node::Literal(
+                word.parse()
+                    .map_err(|_| self.error(&format!("Invalid number `{}`", word)))?,
+            )
+        } else {
+            match lower.as_str() {
+                "i" => Node::Index,
+                "pc" => Node::ProgramCounter,
+                "dt" => Node::DelayTimer,
+                "st" => Node::SoundTimer,
+                "mem" => {
+                    self.rest = rest;
+                    self.expect("[")?;
+                    let addr = self.nested(|parser| parser.parse_binary(0))?;
+                    self.expect("]")?;
+                    return Ok(Node::Memory(Box::new(addr)));
+                }
+                "prev" => {
+                    if self.in_prev {
+                        return Err(self.error("`prev` can't be nested"));
+                    }
+                    self.rest = rest;
+                    self.expect("(")?;
+                    self.in_prev = true;
+                    let node = self.nested(|parser| parser.parse_binary(0));
+                    self.in_prev = false;
+                    let node = node?;
+                    self.expect(")")?;
+                    self.captured.push(node);
+                    return Ok(Node::Prev(self.captured.len() - 1));
+                }
+                _ => match lower.strip_prefix('v').map(|x| u8::from_str_radix(x, 16)) {
+                    Some(Ok(x)) if lower.len() == 2 => Node::Register(x),
+                    _ => return Err(self.error(&format!("Unknown value `{}`", word))),
+                },
+            }
+        };
+
+        self.rest = rest;
+        Ok(node)
+    }
+}
@@ -0,0 +1,220 @@
+use serde::Deserialize;
+
+/// How much scanlines and grid lines darken the pixels they cover
+const LINE_BRIGHTNESS: f32 = 0.45;
+/// The radius in output pixels of the bloom blur
+const BLOOM_RADIUS: usize = 3;
+/// How much of the blurred glow is added back on top of the image
+const BLOOM_STRENGTH: f32 = 0.6;
+
+/// A post-processing filter applied on the CPU while expanding the native display into the
+/// window's frame.
+///
+/// Every filter scales the image up by [`Filter::scale`], and the result is then scaled to the
+/// window as usual.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    /// Plain square pixels
+    #[default]
+    None,
+    /// Darkened horizontal lines between rows of pixels, like a CRT
+    Scanlines,
+    /// Darkened lines between every pixel, like an LCD
+    Grid,
+    /// A soft glow spreading out from lit pixels
+    Bloom,
+    /// Smooths diagonal edges using the Scale2x (EPX) algorithm
+    Scale2x,
+    /// Smooths diagonal edges using the Eagle algorithm
+    Eagle,
+}
+
+impl Filter {
+    /// The number of output pixels per native pixel in each direction
+    pub const fn scale(self) -> usize {
+        match self {
+            Self::None => 1,
+            Self::Scanlines | Self::Grid | Self::Bloom => 4,
+            Self::Scale2x | Self::Eagle => 2,
+        }
+    }
+
+    /// Applies the filter to an RGBA image, writing an image [`Self::scale`] times larger in each
+    /// direction to `dst`.
+    ///
+    /// # Panics
+    ///
+    /// If either buffer is smaller than its dimensions
+    pub fn apply(self, src: &[u8], size: (usize, usize), dst: &mut [u8]) {
+        match self {
+            Self::None => dst[..src.len()].copy_from_slice(src),
+            Self::Scanlines => lines(src, size, dst, self.scale(), false),
+            Self::Grid => lines(src, size, dst, self.scale(), true),
+            Self::Bloom => bloom(src, size, dst, self.scale()),
+            Self::Scale2x => scale2x(src, size, dst),
+            Self::Eagle => eagle(src, size, dst),
+        }
+    }
+}
+
+/// Reads the RGBA pixel at `(x, y)`, clamping coordinates to the edges of the image
+fn pixel_at(src: &[u8], (width, height): (usize, usize), x: isize, y: isize) -> [u8; 4] {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    let start = (y * width + x) * 4;
+    [src[start], src[start + 1], src[start + 2], src[start + 3]]
+}
+
+/// Multiplies the color channels of an RGBA pixel, leaving alpha untouched
+fn darken(pixel: &mut [u8], brightness: f32) {
+    for channel in &mut pixel[..3] {
+        *channel = (*channel as f32 * brightness) as u8;
+    }
+}
+
+/// Writes each source pixel as a `scale * scale` block into `dst`. `block` picks the color of
+/// each output pixel from the source pixel's coordinates and the position within the block.
+fn expand(
+    (width, height): (usize, usize),
+    dst: &mut [u8],
+    scale: usize,
+    block: impl Fn(usize, usize, usize, usize) -> [u8; 4],
+) {
+    let dst_width = width * scale;
+    for y in 0..height * scale {
+        for x in 0..dst_width {
+            let start = (y * dst_width + x) * 4;
+            let color = block(x / scale, y / scale, x % scale, y % scale);
+            dst[start..start + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+/// Expands the image with the last row of every block darkened, and also the last column if
+/// `grid` is set
+fn lines(src: &[u8], size: (usize, usize), dst: &mut [u8], scale: usize, grid: bool) {
+    expand(size, dst, scale, |x, y, block_x, block_y| {
+        let mut pixel = pixel_at(src, size, x as isize, y as isize);
+        if block_y == scale - 1 || (grid && block_x == scale - 1) {
+            darken(&mut pixel, LINE_BRIGHTNESS);
+        }
+        pixel
+    });
+}
+
+/// Expands the image and adds a blurred copy of it on top, so lit pixels glow into their
+/// neighbours
+fn bloom(src: &[u8], size: (usize, usize), dst: &mut [u8], scale: usize) {
+    expand(size, dst, scale, |x, y, _, _| {
+        pixel_at(src, size, x as isize, y as isize)
+    });
+
+    let (width, height) = (size.0 * scale, size.1 * scale);
+    let len = width * height * 4;
+    let sharp = &dst[..len];
+
+    // separable box blur, horizontally then vertically
+    let mut horizontal = vec![0.0f32; len];
+    for y in 0..height {
+        for x in 0..width {
+            let start_x = x.saturating_sub(BLOOM_RADIUS);
+            let end_x = (x + BLOOM_RADIUS).min(width - 1);
+            for channel in 0..3 {
+                let sum: f32 = (start_x..=end_x)
+                    .map(|sx| sharp[(y * width + sx) * 4 + channel] as f32)
+                    .sum();
+                horizontal[(y * width + x) * 4 + channel] = sum / (end_x - start_x + 1) as f32;
+            }
+        }
+    }
+
+    let mut glow = vec![0.0f32; len];
+    for y in 0..height {
+        let start_y = y.saturating_sub(BLOOM_RADIUS);
+        let end_y = (y + BLOOM_RADIUS).min(height - 1);
+        for x in 0..width {
+            for channel in 0..3 {
+                let sum: f32 = (start_y..=end_y)
+                    .map(|sy| horizontal[(sy * width + x) * 4 + channel])
+                    .sum();
+                glow[(y * width + x) * 4 + channel] = sum / (end_y - start_y + 1) as f32;
+            }
+        }
+    }
+
+    for (pixel, glow) in dst[..len].chunks_exact_mut(4).zip(glow.chunks_exact(4)) {
+        for channel in 0..3 {
+            let value = pixel[channel] as f32 + glow[channel] * BLOOM_STRENGTH;
+            pixel[channel] = value.min(255.0) as u8;
+        }
+    }
+}
+
+/// Writes a 2x2 block of output pixels for the source pixel at `(x, y)`
+fn write_block(dst: &mut [u8], width: usize, x: usize, y: usize, block: [[u8; 4]; 4]) {
+    let dst_width = width * 2;
+    for (index, color) in block.iter().enumerate() {
+        let start = ((y * 2 + index / 2) * dst_width + x * 2 + index % 2) * 4;
+        dst[start..start + 4].copy_from_slice(color);
+    }
+}
+
+/// Doubles the image size with the Scale2x (EPX) algorithm, which extends diagonal edges
+/// into the corners of each pixel
+fn scale2x(src: &[u8], size: (usize, usize), dst: &mut [u8]) {
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let at = |dx: isize, dy: isize| pixel_at(src, size, x as isize + dx, y as isize + dy);
+            let p = at(0, 0);
+            let a = at(0, -1);
+            let b = at(1, 0);
+            let c = at(-1, 0);
+            let d = at(0, 1);
+
+            let top_left = if c == a && c != d && a != b { a } else { p };
+            let top_right = if a == b && a != c && b != d { b } else { p };
+            let bottom_left = if d == c && d != b && c != a { c } else { p };
+            let bottom_right = if b == d && b != a && d != c { d } else { p };
+
+            write_block(
+                dst,
+                size.0,
+                x,
+                y,
+                [top_left, top_right, bottom_left, bottom_right],
+            );
+        }
+    }
+}
+
+/// Doubles the image size with the Eagle algorithm, which fills the corner of each pixel when
+/// the three neighbours touching that corner all match
+fn eagle(src: &[u8], size: (usize, usize), dst: &mut [u8]) {
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let at = |dx: isize, dy: isize| pixel_at(src, size, x as isize + dx, y as isize + dy);
+            let center = at(0, 0);
+            let corner = |horizontal: [u8; 4], vertical: [u8; 4], diagonal: [u8; 4]| {
+                if horizontal == vertical && vertical == diagonal {
+                    diagonal
+                } else {
+                    center
+                }
+            };
+
+            let top_left = corner(at(-1, 0), at(0, -1), at(-1, -1));
+            let top_right = corner(at(1, 0), at(0, -1), at(1, -1));
+            let bottom_left = corner(at(-1, 0), at(0, 1), at(-1, 1));
+            let bottom_right = corner(at(1, 0), at(0, 1), at(1, 1));
+
+            write_block(
+                dst,
+                size.0,
+                x,
+                y,
+                [top_left, top_right, bottom_left, bottom_right],
+            );
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use crate::{
+    app::Rom,
+    config::Config,
+    control::{ControlAddress, ControlServer},
+    emulation::EmulationThread,
+    emulator::{Chip8, Quirks},
+    wav::WavWriter,
+};
+
+/// Runs a ROM without a window or audio device for a fixed number of 60Hz frames, as fast as
+/// possible.
+///
+/// Emulated time is all that matters here, so anything recorded comes out the same as if the
+/// ROM had been run in real time. Useful for testing ROMs in CI.
+///
+/// # Arguments
+/// * `rom` - The program to run
+/// * `quirks` - Custom quirks, overriding the defaults
+/// * `config` - Configuration, used for the beep's tone and the decode cache
+/// * `frames` - The number of frames to run for
+/// * `record_audio` - Path to record the sound to as a WAV file, if any
+pub fn run(
+    rom: Rom,
+    quirks: Option<Quirks>,
+    config: &Config,
+    frames: u64,
+    record_audio: Option<&Path>,
+) -> Result<(), String> {
+    let mut emulator = new_emulator(quirks, config);
+
+    if let Some(path) = record_audio {
+        let recorder = WavWriter::create(path)
+            .map_err(|e| format!("Error creating `{}`: {}", path.display(), e))?;
+        emulator.add_audio_sink(Box::new(recorder));
+    }
+
+    log::info!("Running {} headless for {} frames", rom.name, frames);
+    emulator.load(&rom.data);
+
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+
+    emulator
+        .finish_audio()
+        .map_err(|e| format!("Error finishing audio recording: {}", e))
+}
+
+/// Runs the emulator without a window or audio device, controlled by external tools through a
+/// socket. See [`crate::control`]. Only returns if the server fails.
+///
+/// Unlike [`run`], the program runs in real time until a client pauses it.
+///
+/// # Arguments
+/// * `rom` - The program to start running, if any. Otherwise a client has to load one.
+/// * `quirks` - Custom quirks, overriding the defaults
+/// * `config` - Configuration, used for the beep's tone and the decode cache
+/// * `address` - Where to listen for clients
+pub fn serve(
+    rom: Option<Rom>,
+    quirks: Option<Quirks>,
+    config: &Config,
+    address: &ControlAddress,
+) -> Result<(), String> {
+    let mut emulator = new_emulator(quirks, config);
+    if let Some(rom) = &rom {
+        log::info!("Running {} headless", rom.name);
+        emulator.load(&rom.data);
+    }
+
+    let emulation = EmulationThread::spawn(emulator, rom.is_some(), |_| true)
+        .map_err(|e| format!("Error starting emulation thread: {}", e))?;
+    let commands = emulation
+        .sender()
+        .ok_or_else(|| "Emulation thread has stopped".to_string())?;
+    let server = ControlServer::spawn(address, commands)
+        .map_err(|e| format!("Error starting control server: {}", e))?;
+
+    server.join();
+    Err("Control server stopped".to_string())
+}
+
+/// Creates an emulator with no audio sinks. Its random number generator has the default seed, so
+/// clients driving it get the same random numbers every run.
+fn new_emulator(quirks: Option<Quirks>, config: &Config) -> Chip8 {
+    let mut emulator = Chip8::with_tone(config.tone());
+    if let Some(q) = quirks {
+        emulator = emulator.override_quirks(q);
+    }
+
+    if config.decode_cache() {
+        emulator = emulator.with_decode_cache();
+    }
+    emulator
+}
@@ -0,0 +1,254 @@
+use std::fmt::Display;
+
+/// The CHIP-8 variant a program was written for, which decides how opcodes are decoded.
+///
+/// Only the original CHIP-8 instruction set is implemented so far. SUPER-CHIP and XO-CHIP would
+/// be added here, since they give meaning to opcodes that are unused on the original.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// The original COSMAC VIP interpreter
+    #[default]
+    Chip8,
+}
+
+/// A decoded CHIP-8 instruction.
+///
+/// `x` and `y` are register indices (`0x0..=0xF`), `value` is an 8-bit immediate, `addr` is a
+/// 12-bit address, and `rows` is a 4-bit sprite height. Every opcode decodes to something, with
+/// opcodes that aren't instructions on the platform kept as [`Instruction::Unknown`], so that
+/// [`Instruction::encode`] always gives back the original opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `0nnn` - SYS: Calls a machine code routine on the original hardware. Ignored.
+    Sys { addr: u16 },
+    /// `00E0` - CLS: Clears the display
+    Clear,
+    /// `00EE` - RET: Returns from a subroutine
+    Return,
+    /// `1nnn` - JP: Jumps to `addr`
+    Jump { addr: u16 },
+    /// `2nnn` - CALL: Calls the subroutine at `addr`
+    Call { addr: u16 },
+    /// `3xkk` - SE: Skips the next instruction if `Vx == value`
+    SkipIfEqual { x: u8, value: u8 },
+    /// `4xkk` - SNE: Skips the next instruction if `Vx != value`
+    SkipIfNotEqual { x: u8, value: u8 },
+    /// `5xy0` - SE: Skips the next instruction if `Vx == Vy`
+    SkipIfRegistersEqual { x: u8, y: u8 },
+    /// `6xkk` - LD: Sets `Vx = value`
+    Load { x: u8, value: u8 },
+    /// `7xkk` - ADD: Sets `Vx = Vx + value`, without setting the carry flag
+    Add { x: u8, value: u8 },
+    /// `8xy0` - LD: Sets `Vx = Vy`
+    Move { x: u8, y: u8 },
+    /// `8xy1` - OR: Sets `Vx = Vx | Vy`
+    Or { x: u8, y: u8 },
+    /// `8xy2` - AND: Sets `Vx = Vx & Vy`
+    And { x: u8, y: u8 },
+    /// `8xy3` - XOR: Sets `Vx = Vx ^ Vy`
+    Xor { x: u8, y: u8 },
+    /// `8xy4` - ADD: Sets `Vx = Vx + Vy` and `VF` to the carry
+    AddRegisters { x: u8, y: u8 },
+    /// `8xy5` - SUB: Sets `Vx = Vx - Vy` and `VF` to NOT borrow
+    Sub { x: u8, y: u8 },
+    /// `8xy6` - SHR: Shifts right by one, see [`crate::emulator::Quirks::SHIFTING`]
+    ShiftRight { x: u8, y: u8 },
+    /// `8xy7` - SUBN: Sets `Vx = Vy - Vx` and `VF` to NOT borrow
+    SubReversed { x: u8, y: u8 },
+    /// `8xyE` - SHL: Shifts left by one, see [`crate::emulator::Quirks::SHIFTING`]
+    ShiftLeft { x: u8, y: u8 },
+    /// `9xy0` - SNE: Skips the next instruction if `Vx != Vy`
+    SkipIfRegistersNotEqual { x: u8, y: u8 },
+    /// `Annn` - LD I: Sets `I = addr`
+    LoadIndex { addr: u16 },
+    /// `Bnnn` - JP V0: Jumps to `addr + V0`, see [`crate::emulator::Quirks::JUMPING`]
+    JumpOffset { addr: u16 },
+    /// `Cxkk` - RND: Sets `Vx` to a random byte ANDed with `value`
+    Random { x: u8, value: u8 },
+    /// `Dxyn` - DRW: Draws a sprite `rows` tall from memory at `I` at `(Vx, Vy)`, setting `VF`
+    /// on collision
+    Draw { x: u8, y: u8, rows: u8 },
+    /// `Ex9E` - SKP: Skips the next instruction if the key in `Vx` is pressed
+    SkipIfKey { x: u8 },
+    /// `ExA1` - SKNP: Skips the next instruction if the key in `Vx` isn't pressed
+    SkipIfNotKey { x: u8 },
+    /// `Fx07` - LD DT: Sets `Vx` to the delay timer
+    LoadDelayTimer { x: u8 },
+    /// `Fx0A` - LD K: Waits for a key to be pressed and released, storing it in `Vx`
+    WaitForKey { x: u8 },
+    /// `Fx15` - LD DT: Sets the delay timer to `Vx`
+    SetDelayTimer { x: u8 },
+    /// `Fx18` - LD ST: Sets the sound timer to `Vx`
+    SetSoundTimer { x: u8 },
+    /// `Fx1E` - ADD I: Sets `I = I + Vx`
+    AddIndex { x: u8 },
+    /// `Fx29` - LD F: Points `I` at the font sprite for the digit in `Vx`
+    LoadFont { x: u8 },
+    /// `Fx33` - LD B: Stores the decimal digits of `Vx` at `I`, `I + 1` and `I + 2`
+    StoreDecimal { x: u8 },
+    /// `Fx55` - LD [I]: Stores `V0` to `Vx` in memory from `I`, see
+    /// [`crate::emulator::Quirks::MEMORY`]
+    StoreRegisters { x: u8 },
+    /// `Fx65` - LD [I]: Loads `V0` to `Vx` from memory at `I`, see
+    /// [`crate::emulator::Quirks::MEMORY`]
+    LoadRegisters { x: u8 },
+    /// An opcode that isn't an instruction on the platform
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decodes an opcode for the given platform
+    pub fn decode(opcode: u16, platform: Platform) -> Self {
+        match platform {
+            Platform::Chip8 => Self::decode_chip8(opcode),
+        }
+    }
+
+    /// Decodes an opcode of the original CHIP-8 instruction set
+    fn decode_chip8(opcode: u16) -> Self {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let value = (opcode & 0x00FF) as u8;
+        let addr = opcode & 0x0FFF;
+
+        match (opcode >> 12, x, y, n) {
+            (0, 0, 0xE, 0) => Self::Clear,
+            (0, 0, 0xE, 0xE) => Self::Return,
+            (0, _, _, _) => Self::Sys { addr },
+            (1, _, _, _) => Self::Jump { addr },
+            (2, _, _, _) => Self::Call { addr },
+            (3, _, _, _) => Self::SkipIfEqual { x, value },
+            (4, _, _, _) => Self::SkipIfNotEqual { x, value },
+            (5, _, _, 0) => Self::SkipIfRegistersEqual { x, y },
+            (6, _, _, _) => Self::Load { x, value },
+            (7, _, _, _) => Self::Add { x, value },
+            (8, _, _, 0) => Self::Move { x, y },
+            (8, _, _, 1) => Self::Or { x, y },
+            (8, _, _, 2) => Self::And { x, y },
+            (8, _, _, 3) => Self::Xor { x, y },
+            (8, _, _, 4) => Self::AddRegisters { x, y },
+            (8, _, _, 5) => Self::Sub { x, y },
+            (8, _, _, 6) => Self::ShiftRight { x, y },
+            (8, _, _, 7) => Self::SubReversed { x, y },
+            (8, _, _, 0xE) => Self::ShiftLeft { x, y },
+            (9, _, _, 0) => Self::SkipIfRegistersNotEqual { x, y },
+            (0xA, _, _, _) => Self::LoadIndex { addr },
+            (0xB, _, _, _) => Self::JumpOffset { addr },
+            (0xC, _, _, _) => Self::Random { x, value },
+            (0xD, _, _, rows) => Self::Draw { x, y, rows },
+            (0xE, _, 9, 0xE) => Self::SkipIfKey { x },
+            (0xE, _, 0xA, 1) => Self::SkipIfNotKey { x },
+            (0xF, _, 0, 7) => Self::LoadDelayTimer { x },
+            (0xF, _, 0, 0xA) => Self::WaitForKey { x },
+            (0xF, _, 1, 5) => Self::SetDelayTimer { x },
+            (0xF, _, 1, 8) => Self::SetSoundTimer { x },
+            (0xF, _, 1, 0xE) => Self::AddIndex { x },
+            (0xF, _, 2, 9) => Self::LoadFont { x },
+            (0xF, _, 3, 3) => Self::StoreDecimal { x },
+            (0xF, _, 5, 5) => Self::StoreRegisters { x },
+            (0xF, _, 6, 5) => Self::LoadRegisters { x },
+            _ => Self::Unknown(opcode),
+        }
+    }
+
+    /// Encodes the instruction back into its opcode. Fields are masked to the bits available
+    /// in the opcode.
+    pub fn encode(self) -> u16 {
+        /// Builds an opcode from its four nibbles
+        fn nibbles(a: u16, b: u8, c: u8, d: u8) -> u16 {
+            a << 12 | (b as u16 & 0xF) << 8 | (c as u16 & 0xF) << 4 | d as u16 & 0xF
+        }
+        /// Builds an opcode from its top nibble and a 12-bit address
+        fn address(a: u16, addr: u16) -> u16 {
+            a << 12 | addr & 0xFFF
+        }
+        /// Builds an opcode from its top nibble, a register, and an 8-bit value
+        fn immediate(a: u16, x: u8, value: u8) -> u16 {
+            a << 12 | (x as u16 & 0xF) << 8 | value as u16
+        }
+
+        match self {
+            Self::Sys { addr } => address(0, addr),
+            Self::Clear => 0x00E0,
+            Self::Return => 0x00EE,
+            Self::Jump { addr } => address(1, addr),
+            Self::Call { addr } => address(2, addr),
+            Self::SkipIfEqual { x, value } => immediate(3, x, value),
+            Self::SkipIfNotEqual { x, value } => immediate(4, x, value),
+            Self::SkipIfRegistersEqual { x, y } => nibbles(5, x, y, 0),
+            Self::Load { x, value } => immediate(6, x, value),
+            Self::Add { x, value } => immediate(7, x, value),
+            Self::Move { x, y } => nibbles(8, x, y, 0),
+            Self::Or { x, y } => nibbles(8, x, y, 1),
+            Self::And { x, y } => nibbles(8, x, y, 2),
+            Self::Xor { x, y } => nibbles(8, x, y, 3),
+            Self::AddRegisters { x, y } => nibbles(8, x, y, 4),
+            Self::Sub { x, y } => nibbles(8, x, y, 5),
+            Self::ShiftRight { x, y } => nibbles(8, x, y, 6),
+            Self::SubReversed { x, y } => nibbles(8, x, y, 7),
+            Self::ShiftLeft { x, y } => nibbles(8, x, y, 0xE),
+            Self::SkipIfRegistersNotEqual { x, y } => nibbles(9, x, y, 0),
+            Self::LoadIndex { addr } => address(0xA, addr),
+            Self::JumpOffset { addr } => address(0xB, addr),
+            Self::Random { x, value } => immediate(0xC, x, value),
+            Self::Draw { x, y, rows } => nibbles(0xD, x, y, rows),
+            Self::SkipIfKey { x } => nibbles(0xE, x, 9, 0xE),
+            Self::SkipIfNotKey { x } => nibbles(0xE, x, 0xA, 1),
+            Self::LoadDelayTimer { x } => nibbles(0xF, x, 0, 7),
+            Self::WaitForKey { x } => nibbles(0xF, x, 0, 0xA),
+            Self::SetDelayTimer { x } => nibbles(0xF, x, 1, 5),
+            Self::SetSoundTimer { x } => nibbles(0xF, x, 1, 8),
+            Self::AddIndex { x } => nibbles(0xF, x, 1, 0xE),
+            Self::LoadFont { x } => nibbles(0xF, x, 2, 9),
+            Self::StoreDecimal { x } => nibbles(0xF, x, 3, 3),
+            Self::StoreRegisters { x } => nibbles(0xF, x, 5, 5),
+            Self::LoadRegisters { x } => nibbles(0xF, x, 6, 5),
+            Self::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+/// Formats the instruction as assembly, using the common Cowgod mnemonics
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Sys { addr } => write!(f, "SYS 0x{:03x}", addr),
+            Self::Clear => write!(f, "CLS"),
+            Self::Return => write!(f, "RET"),
+            Self::Jump { addr } => write!(f, "JP 0x{:03x}", addr),
+            Self::Call { addr } => write!(f, "CALL 0x{:03x}", addr),
+            Self::SkipIfEqual { x, value } => write!(f, "SE V{:X}, {}", x, value),
+            Self::SkipIfNotEqual { x, value } => write!(f, "SNE V{:X}, {}", x, value),
+            Self::SkipIfRegistersEqual { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::Load { x, value } => write!(f, "LD V{:X}, {}", x, value),
+            Self::Add { x, value } => write!(f, "ADD V{:X}, {}", x, value),
+            Self::Move { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddRegisters { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::SubReversed { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SkipIfRegistersNotEqual { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::LoadIndex { addr } => write!(f, "LD I, 0x{:03x}", addr),
+            Self::JumpOffset { addr } => write!(f, "JP V0, 0x{:03x}", addr),
+            Self::Random { x, value } => write!(f, "RND V{:X}, {}", x, value),
+            Self::Draw { x, y, rows } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, rows),
+            Self::SkipIfKey { x } => write!(f, "SKP V{:X}", x),
+            Self::SkipIfNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Self::LoadDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Self::WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            Self::SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            Self::SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            Self::AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Self::LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            Self::StoreDecimal { x } => write!(f, "LD B, V{:X}", x),
+            Self::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Self::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Self::Unknown(opcode) => write!(f, "DW 0x{:04x}", opcode),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, de::IntoDeserializer};
+use winit::keyboard::KeyCode;
+
+/// The default layout, mapping the block of keys at the `1234/qwer/asdf/zxcv` positions of a
+/// QWERTY keyboard onto the CHIP-8 keypad. Keys are bound by position, so the same block is used
+/// on every keyboard layout.
+const DEFAULT_LAYOUT: [(KeyCode, usize); 16] = [
+    (KeyCode::Digit1, 0x1),
+    (KeyCode::Digit2, 0x2),
+    (KeyCode::Digit3, 0x3),
+    (KeyCode::Digit4, 0xC),
+    (KeyCode::KeyQ, 0x4),
+    (KeyCode::KeyW, 0x5),
+    (KeyCode::KeyE, 0x6),
+    (KeyCode::KeyR, 0xD),
+    (KeyCode::KeyA, 0x7),
+    (KeyCode::KeyS, 0x8),
+    (KeyCode::KeyD, 0x9),
+    (KeyCode::KeyF, 0xE),
+    (KeyCode::KeyZ, 0xA),
+    (KeyCode::KeyX, 0x0),
+    (KeyCode::KeyC, 0xB),
+    (KeyCode::KeyV, 0xF),
+];
+
+/// Parses a CHIP-8 key index from a single hex digit (`0`-`F`)
+fn parse_key_index(s: &str) -> Result<usize, String> {
+    match usize::from_str_radix(s, 16) {
+        Ok(index) if s.len() == 1 => Ok(index),
+        _ => Err(format!("`{}` is not a CHIP-8 key, expected 0-F", s)),
+    }
+}
+
+/// Parses a physical key code.
+///
+/// Accepts winit's [`KeyCode`] names (e.g. `KeyQ`, `Digit1`, `ArrowUp`), as well as single letters
+/// and digits as a shorthand for the key at that position on a QWERTY keyboard.
+fn parse_key_code(s: &str) -> Result<KeyCode, String> {
+    let shorthand = match s.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => format!("Key{}", c.to_ascii_uppercase() as char),
+        [c] if c.is_ascii_digit() => format!("Digit{}", *c as char),
+        _ => s.to_string(),
+    };
+
+    KeyCode::deserialize(shorthand.as_str().into_deserializer())
+        .map_err(|_: serde::de::value::Error| format!("`{}` is not a known key code", s))
+}
+
+/// A single key or a list of keys, so that config files can write either `1 = "x"` or
+/// `1 = ["x", "y"]`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A set of key bindings, mapping CHIP-8 key indices to the physical keys that press them.
+///
+/// Bindings for a CHIP-8 key replace all previous bindings for that key when applied to a
+/// [`KeyMap`], so a layout only has to mention the keys it changes.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(try_from = "BTreeMap<String, OneOrMany>")]
+pub struct KeyBindings {
+    bindings: BTreeMap<usize, Vec<KeyCode>>,
+}
+
+impl KeyBindings {
+    /// Binds the given host keys to a CHIP-8 key, replacing any keys previously bound to it
+    pub fn bind(&mut self, key_index: usize, keys: Vec<KeyCode>) {
+        self.bindings.insert(key_index, keys);
+    }
+
+    /// Adds all bindings from `other`, replacing those for CHIP-8 keys that are bound in both
+    pub fn extend(&mut self, other: KeyBindings) {
+        self.bindings.extend(other.bindings);
+    }
+}
+
+impl TryFrom<BTreeMap<String, OneOrMany>> for KeyBindings {
+    type Error = String;
+
+    fn try_from(value: BTreeMap<String, OneOrMany>) -> Result<Self, Self::Error> {
+        let mut bindings = Self::default();
+        for (key, keys) in value {
+            let keys = match keys {
+                OneOrMany::One(key) => vec![parse_key_code(&key)?],
+                OneOrMany::Many(keys) => keys
+                    .iter()
+                    .map(|k| parse_key_code(k))
+                    .collect::<Result<_, _>>()?,
+            };
+            bindings.bind(parse_key_index(&key)?, keys);
+        }
+
+        Ok(bindings)
+    }
+}
+
+/// Parses a key binding from the command line in the form `KEY=HOST_KEY[,HOST_KEY...]`, where
+/// `KEY` is a CHIP-8 key from `0` to `F`
+pub fn parse_binding(s: &str) -> Result<KeyBindings, String> {
+    let Some((key, host_keys)) = s.split_once('=') else {
+        return Err(format!(
+            "`{}` is not a key binding, expected KEY=HOST_KEY",
+            s
+        ));
+    };
+
+    let host_keys = host_keys
+        .split(',')
+        .filter(|k| !k.is_empty())
+        .map(parse_key_code)
+        .collect::<Result<_, _>>()?;
+
+    let mut bindings = KeyBindings::default();
+    bindings.bind(parse_key_index(key)?, host_keys);
+    Ok(bindings)
+}
+
+/// Maps physical host keys to CHIP-8 key indices
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    /// Lookup from a host key to the CHIP-8 key it presses
+    keys: HashMap<KeyCode, usize>,
+}
+
+impl KeyMap {
+    /// Applies a set of bindings on top of this map.
+    ///
+    /// Every CHIP-8 key mentioned in `bindings` loses its previous host keys first.
+    pub fn apply(&mut self, bindings: &KeyBindings) {
+        for (&key_index, host_keys) in &bindings.bindings {
+            self.keys.retain(|_, index| *index != key_index);
+            for &host_key in host_keys {
+                self.keys.insert(host_key, key_index);
+            }
+        }
+    }
+
+    /// Maps a given host key to a CHIP-8 key index
+    pub fn get(&self, key: KeyCode) -> Option<usize> {
+        self.keys.get(&key).copied()
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            keys: DEFAULT_LAYOUT.into_iter().collect(),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! A CHIP-8 emulator.
+//!
+//! The emulator core is [`emulator::Chip8`], which has no dependency on a window or audio
+//! device. The rest of the crate is the frontend used by the `chip8-rs` binary.
+
+#![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
+
+pub mod app;
+pub mod audio;
+pub mod bench;
+pub mod config;
+pub mod control;
+mod emulation;
+pub mod emulator;
+pub mod environment;
+pub mod expression;
+pub mod filter;
+pub mod headless;
+pub mod instruction;
+pub mod keymap;
+pub mod machine;
+pub mod memory;
+pub mod palette;
+pub mod render;
+mod rng;
+mod scheduler;
+mod stack;
+pub mod tone;
+pub mod virtual_buffer;
+pub mod wav;
+
+#[cfg(feature = "audio")]
+mod sound;
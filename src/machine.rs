@@ -0,0 +1,158 @@
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    emulator::{FRAME_PERIOD, START_ADDR},
+    memory::Memory,
+    rng::Xorshift,
+    stack::Stack,
+    virtual_buffer::VirtualDisplay,
+};
+
+/// The state of the emulated machine, separate from the emulator's settings and I/O devices.
+///
+/// Everything that affects what the program does next is here, including the state of the random
+/// number generator used by `Cxkk`, so two machines that compare equal behave identically when run
+/// by emulators with the same settings and given the same keys. Cloning is cheap since
+/// nothing is heap allocated, which makes it practical to fork the machine to try out inputs, or
+/// to hash machines to skip states that have already been explored.
+///
+/// Take the machine from an emulator with [`crate::emulator::Chip8::machine`] and put it back
+/// with [`crate::emulator::Chip8::set_machine`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Machine {
+    /// The RAM
+    pub(crate) memory: Memory,
+    /// 16 VX registers
+    pub(crate) v_registers: [u8; 16],
+    /// Delay timer
+    pub(crate) delay_timer: u8,
+    /// Sound timer
+    pub(crate) sound_timer: u8,
+    /// Program counter
+    pub(crate) program_counter: u16,
+    /// Special index (I) register
+    pub(crate) index_register: u16,
+    /// The stack
+    pub(crate) stack: Stack,
+    /// The virtualized window display buffer
+    pub(crate) window: VirtualDisplay,
+    /// Array of which keys are currently pressed
+    pub(crate) keys: [bool; 16],
+    /// This is Some when we are waiting on a keypress from the FX0A instruction
+    pub(crate) key_wait_register: Option<u8>,
+    /// Signifies when we are waiting for the next VBlank, see
+    /// [`crate::emulator::Quirks::DISPLAY_WAIT`]
+    pub(crate) waiting_for_vblank: bool,
+    /// The number of frames run since the last reset, which decides how many cycles the next
+    /// frame runs
+    pub(crate) frame: FrameCount,
+    /// Generates the random numbers for `Cxkk`
+    pub(crate) rng: Xorshift,
+}
+
+impl Machine {
+    /// Creates a machine in its power-on state, with the font loaded and nothing else
+    pub fn new() -> Self {
+        Self {
+            memory: Memory::new(),
+            v_registers: [0; 16],
+            delay_timer: 0,
+            sound_timer: 0,
+            program_counter: START_ADDR,
+            index_register: 0,
+            stack: Stack::new(),
+            window: VirtualDisplay::new(),
+            keys: [false; 16],
+            key_wait_register: None,
+            waiting_for_vblank: false,
+            frame: FrameCount(0),
+            rng: Xorshift::default(),
+        }
+    }
+
+    /// The display
+    pub fn window(&self) -> &VirtualDisplay {
+        &self.window
+    }
+
+    /// The VX registers
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v_registers
+    }
+
+    /// The index (I) register
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    /// The program counter
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// Return addresses on the stack, from the bottom up
+    pub fn stack(&self) -> &[u16] {
+        self.stack.as_slice()
+    }
+
+    /// The delay timer
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// The sound timer
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// The whole of memory
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    /// Which keys are held down
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// The number of frames run since the last reset
+    pub fn frame(&self) -> u64 {
+        self.frame.0
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The number of frames run since the last reset.
+///
+/// Only where the count is in the repeating pattern of [`crate::emulator::cycles_in_frame`]
+/// affects what the program does, so counts compare equal and hash the same when they're at the
+/// same point in the pattern. Otherwise a machine would never compare equal to one from an
+/// earlier frame, even if the program had stopped changing anything else.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameCount(pub(crate) u64);
+
+impl FrameCount {
+    /// Where the count is in the pattern of cycles per frame
+    fn phase(self) -> u64 {
+        self.0 % FRAME_PERIOD
+    }
+}
+
+impl PartialEq for FrameCount {
+    fn eq(&self, other: &Self) -> bool {
+        self.phase() == other.phase()
+    }
+}
+
+impl Eq for FrameCount {}
+
+impl Hash for FrameCount {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.phase().hash(state);
+    }
+}
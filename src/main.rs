@@ -1,21 +1,31 @@
 #![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 
-use clap::Parser;
-use winit::event_loop::{ControlFlow, EventLoop};
-
-use crate::{app::App, emulator::Quirks};
+use std::path::PathBuf;
 
-mod app;
-mod emulator;
-mod memory;
-mod stack;
-mod virtual_buffer;
+use clap::{Parser, Subcommand};
+use winit::event_loop::{ControlFlow, EventLoop};
 
-#[cfg(feature = "audio")]
-mod sound;
+use chip8_rs::{
+    app::{App, Rom},
+    bench::{self, DisplayChoice},
+    config::Config,
+    control::{self, ControlAddress},
+    emulator::Quirks,
+    filter::Filter,
+    headless,
+    keymap::{self, KeyBindings},
+    palette::{self, Palette},
+    render::ScalingMode,
+    tone::{self, Waveform},
+};
 
 fn parse_quirk(s: &str) -> Result<Quirks, String> {
-    match s.to_lowercase().as_str() {
+    let s = s.to_lowercase();
+    if let Some((_, quirks)) = Quirks::PROFILES.iter().find(|(name, _)| *name == s) {
+        return Ok(*quirks);
+    }
+
+    match s.as_str() {
         "vf-reset" => Ok(Quirks::VF_RESET),
         "memory" => Ok(Quirks::MEMORY),
         "display-wait" => Ok(Quirks::DISPLAY_WAIT),
@@ -26,17 +36,139 @@ fn parse_quirk(s: &str) -> Result<Quirks, String> {
     }
 }
 
+/// Tools other than running a ROM normally
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run a ROM as fast as possible and report how quickly it was emulated
+    Bench {
+        /// Path to the CHIP-8 ROM to benchmark
+        rom: PathBuf,
+
+        /// The number of CPU cycles to run for
+        #[arg(long, value_name = "N", default_value_t = 10_000_000,
+              value_parser = clap::value_parser!(u64).range(1..))]
+        cycles: u64,
+
+        /// Set custom CHIP-8 quirks. Can be repeated.
+        #[arg(long="quirk", short='q', value_name="QUIRK_NAME", value_parser = parse_quirk)]
+        quirks: Vec<Quirks>,
+
+        /// Cache decoded instructions
+        #[arg(long)]
+        decode_cache: bool,
+
+        /// The display backends to time drawing with
+        #[arg(long, value_enum, value_name = "BACKEND", default_value_t = DisplayChoice::All)]
+        display: DisplayChoice,
+    },
+}
+
+/// Combines quirk flags from the command line, or `None` if there aren't any
+fn combine_quirks(quirks: Vec<Quirks>) -> Option<Quirks> {
+    if quirks.is_empty() {
+        None
+    } else {
+        Some(
+            quirks
+                .into_iter()
+                .fold(Quirks::empty(), |acc, quirk| acc | quirk),
+        )
+    }
+}
+
 /// Defines this program's command-line arguments
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Path to the CHIP-8 ROM to load
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Path to the CHIP-8 ROM to load. If omitted, a ROM can be dropped onto the window instead.
     #[arg(index = 1)]
-    input_file: String,
+    input_file: Option<PathBuf>,
+
+    /// Path to a TOML configuration file
+    #[arg(long, short = 'c', value_name = "PATH")]
+    config: Option<PathBuf>,
 
     /// Set custom CHIP-8 quirks. Can be repeated.
     /// Options: vf-reset, memory, clipping, shifting, jumping, display-wait
     #[arg(long="quirk", short='q', value_name="QUIRK_NAME", value_parser = parse_quirk)]
     quirks: Vec<Quirks>,
+
+    /// Bind host keys to a CHIP-8 key (0-F), replacing its default keys. Can be repeated.
+    /// Example: `--bind 4=q,a` presses key 4 with either Q or A
+    #[arg(long = "bind", short = 'b', value_name = "KEY=HOST_KEYS", value_parser = keymap::parse_binding)]
+    bindings: Vec<KeyBindings>,
+
+    /// The display colors. Either a preset (default, amber, green, lcd, high-contrast) or a comma
+    /// separated pair of hex colors, e.g. `#000000,#33ff66`
+    #[arg(long, short = 'p', value_name = "PALETTE", value_parser = palette::parse_palette)]
+    palette: Option<Palette>,
+
+    /// How the display is scaled to fit the window
+    #[arg(long, value_enum, value_name = "MODE")]
+    scaling: Option<ScalingMode>,
+
+    /// Start in fullscreen. Fullscreen can also be toggled with F11
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Emulate CRT phosphor persistence, fading pixels out over the given number of frames
+    /// instead of turning them off instantly. Reduces flicker in games that redraw sprites
+    /// constantly. 0 disables it
+    #[arg(long, value_name = "FRAMES")]
+    persistence: Option<u32>,
+
+    /// Post-processing filter applied to the display on the CPU
+    #[arg(long, value_enum, value_name = "FILTER")]
+    filter: Option<Filter>,
+
+    /// Pitch of the beep in Hz. Around 1500 with a square wave is closest to real hardware
+    #[arg(long, value_name = "HZ", value_parser = tone::parse_frequency)]
+    frequency: Option<f32>,
+
+    /// Waveform of the beep
+    #[arg(long, value_enum, value_name = "WAVEFORM")]
+    waveform: Option<Waveform>,
+
+    /// Volume of the beep, from 0.0 to 1.0
+    #[arg(long, value_name = "VOLUME", value_parser = tone::parse_volume)]
+    volume: Option<f32>,
+
+    /// Start with the beep muted. Muting can also be toggled with F3
+    #[arg(long)]
+    mute: bool,
+
+    /// Record the sound the program makes to a WAV file. The sound is generated in emulated time,
+    /// so the recording doesn't depend on an audio device
+    #[arg(long, value_name = "PATH")]
+    record_audio: Option<PathBuf>,
+
+    /// Run the program without a window or audio device for a fixed number of frames, as fast as
+    /// possible. Useful with `--record-audio` for testing in CI. With `--control`, runs in real
+    /// time until stopped instead
+    #[arg(long)]
+    headless: bool,
+
+    /// Let external tools control the emulator with JSON requests over a socket, either
+    /// `HOST:PORT` or `unix:PATH`. See the README for the protocol
+    #[arg(long, value_name = "ADDRESS", value_parser = control::parse_address)]
+    control: Option<ControlAddress>,
+
+    /// Cache decoded instructions to speed up the interpreter, at the cost of some memory
+    #[arg(long)]
+    decode_cache: bool,
+
+    /// The number of 60Hz frames to run for in headless mode
+    #[arg(
+        long,
+        value_name = "FRAMES",
+        default_value_t = 600,
+        requires = "headless",
+        conflicts_with = "control"
+    )]
+    frames: u64,
 }
 
 fn main() {
@@ -45,31 +177,136 @@ fn main() {
 
     let args = Args::parse();
 
-    let custom_quirks = if args.quirks.is_empty() {
-        None
-    } else {
-        Some(
-            args.quirks
-                .into_iter()
-                .fold(Quirks::empty(), |acc, quirk| acc | quirk),
-        )
-    };
+    if let Some(Commands::Bench {
+        rom,
+        cycles,
+        quirks,
+        decode_cache,
+        display,
+    }) = args.command
+    {
+        let rom = match Rom::read(&rom) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error reading `{}`: {:?}", rom.display(), e);
+                std::process::exit(1);
+            }
+        };
+        bench::run(&rom, combine_quirks(quirks), cycles, decode_cache, display);
+        return;
+    }
+
+    let custom_quirks = combine_quirks(args.quirks);
 
     if let Some(q) = custom_quirks {
         log::info!("Using custom quirks: {:?}", q);
     }
 
-    log::info!("Loading program from: {}", args.input_file);
-    let data = match std::fs::read(args.input_file) {
-        Ok(v) => v,
-        Err(e) => {
-            log::error!("Expected a path to a CHIP-8 program");
-            log::error!("{:?}", e);
-            std::process::exit(1);
+    let mut config = match &args.config {
+        Some(path) => {
+            log::info!("Loading config from: {}", path.display());
+            match Config::load(path) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::error!("{}", e);
+                    std::process::exit(1);
+                }
+            }
         }
+        None => Config::default(),
     };
 
-    let event_loop = match EventLoop::new() {
+    for bindings in args.bindings {
+        config.override_keys(bindings);
+    }
+
+    if let Some(palette) = args.palette {
+        config.override_palette(palette);
+    }
+
+    if let Some(scaling) = args.scaling {
+        config.override_scaling(scaling);
+    }
+
+    if args.fullscreen {
+        config.enable_fullscreen();
+    }
+
+    if args.decode_cache {
+        config.enable_decode_cache();
+    }
+
+    if let Some(frames) = args.persistence {
+        config.override_persistence(frames);
+    }
+
+    if let Some(filter) = args.filter {
+        config.override_filter(filter);
+    }
+
+    if let Some(frequency) = args.frequency {
+        config.override_frequency(frequency);
+    }
+
+    if let Some(waveform) = args.waveform {
+        config.override_waveform(waveform);
+    }
+
+    if let Some(volume) = args.volume {
+        config.override_volume(volume);
+    }
+
+    if args.mute {
+        config.enable_mute();
+    }
+
+    let rom = args.input_file.map(|input_file| {
+        log::info!("Loading program from: {}", input_file.display());
+        match Rom::read(&input_file) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Expected a path to a CHIP-8 program");
+                log::error!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    if args.headless
+        && let Some(address) = &args.control
+    {
+        if args.record_audio.is_some() {
+            log::error!("Audio can't be recorded while running headless with `--control`");
+            std::process::exit(1);
+        }
+
+        if let Err(e) = headless::serve(rom, custom_quirks, &config, address) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.headless {
+        let Some(rom) = rom else {
+            log::error!("A ROM is required to run headless without `--control`");
+            std::process::exit(1);
+        };
+
+        if let Err(e) = headless::run(
+            rom,
+            custom_quirks,
+            &config,
+            args.frames,
+            args.record_audio.as_deref(),
+        ) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let event_loop = match EventLoop::with_user_event().build() {
         Ok(v) => v,
         Err(e) => {
             log::error!("Error creating event loop: {:?}", e);
@@ -78,7 +315,21 @@ fn main() {
     };
     event_loop.set_control_flow(ControlFlow::Wait);
 
-    let mut app = App::new(data, custom_quirks);
+    let mut app = App::new(rom, custom_quirks, config, event_loop.create_proxy());
+    if let Some(path) = &args.record_audio
+        && let Err(e) = app.record_audio(path)
+    {
+        log::error!("Error creating `{}`: {}", path.display(), e);
+        std::process::exit(1);
+    }
+
+    if let Some(address) = &args.control
+        && let Err(e) = app.serve_control(address)
+    {
+        log::error!("Error starting control server: {}", e);
+        std::process::exit(1);
+    }
+
     if let Err(e) = event_loop.run_app(&mut app) {
         log::error!("Error running event loop: {:?}", e);
         std::process::exit(1);
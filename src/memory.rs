@@ -1,7 +1,7 @@
 use std::ops::{Index, IndexMut, Range};
 
 /// The size of the CHIP-8 RAM
-const MEMORY_SIZE: usize = 4096;
+pub const MEMORY_SIZE: usize = 4096;
 
 /// Font for characters `0x0`-`0xF`
 const FONT_BYTES: [u8; 80] = [
@@ -24,7 +24,7 @@ const FONT_BYTES: [u8; 80] = [
 ];
 
 /// Represents the CHIP-8's memory
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Memory {
     memory: [u8; MEMORY_SIZE],
 }
@@ -38,6 +38,11 @@ impl Memory {
 
         Self { memory }
     }
+
+    /// Returns the whole of memory as a slice
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
 }
 
 impl Index<usize> for Memory {
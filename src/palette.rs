@@ -0,0 +1,88 @@
+use serde::Deserialize;
+
+/// A pair of RGBA colors used to render the display, for pixels that are off and on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Palette {
+    off: u32,
+    on: u32,
+}
+
+/// The built-in palettes, selectable by name
+pub const PRESETS: [(&str, Palette); 5] = [
+    ("default", Palette::new(0x1A1A1AFF, 0xFFFFFFFF)),
+    ("amber", Palette::new(0x1A0F00FF, 0xFFB000FF)),
+    ("green", Palette::new(0x0A1A0AFF, 0x33FF66FF)),
+    ("lcd", Palette::new(0x9BBC0FFF, 0x0F380FFF)),
+    ("high-contrast", Palette::new(0x000000FF, 0xFFFFFFFF)),
+];
+
+impl Palette {
+    /// Constructs a palette from the RGBA colors of pixels that are off and on
+    pub const fn new(off: u32, on: u32) -> Self {
+        Self { off, on }
+    }
+
+    /// The RGBA value of a pixel being off
+    pub const fn off(&self) -> u32 {
+        self.off
+    }
+
+    /// The RGBA value of a pixel being on
+    pub const fn on(&self) -> u32 {
+        self.on
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+impl TryFrom<String> for Palette {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        parse_palette(&value)
+    }
+}
+
+/// Parses a hex color in the form `RRGGBB` or `#RRGGBB` into an opaque RGBA value
+fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok((rgb << 8) | 0xFF),
+        _ => Err(format!("`{}` is not a hex color, expected RRGGBB", s)),
+    }
+}
+
+/// Parses a palette from either a preset name or two comma separated hex colors, off then on
+pub fn parse_palette(s: &str) -> Result<Palette, String> {
+    if let Some((_, palette)) = PRESETS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(*palette);
+    }
+
+    let colors = if s.contains(',') {
+        s.split(',')
+            .map(|c| parse_color(c.trim()))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        Vec::new()
+    };
+
+    match colors[..] {
+        [off, on] => Ok(Palette::new(off, on)),
+        _ => {
+            let names: Vec<_> = PRESETS.iter().map(|(name, _)| *name).collect();
+            Err(format!(
+                "`{}` is not a palette, expected 2 hex colors or one of: {}",
+                s,
+                names.join(", ")
+            ))
+        }
+    }
+}
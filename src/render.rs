@@ -0,0 +1,243 @@
+use serde::Deserialize;
+
+use crate::{filter::Filter, palette::Palette, virtual_buffer::VirtualDisplay};
+
+/// How the display is scaled up to fill the window.
+///
+/// Either way the display keeps its aspect ratio, and the rest of the window is filled with
+/// letterbox bars.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScalingMode {
+    /// Scale by the largest whole number that fits, so every CHIP-8 pixel is the same size
+    #[default]
+    Integer,
+    /// Scale to fill as much of the window as possible, even if pixels end up uneven in size
+    Fit,
+}
+
+impl ScalingMode {
+    /// Returns the size of the pixel buffer to render into for a display of size `native` shown
+    /// on a surface of size `surface`.
+    ///
+    /// Integer scaling is left to the GPU, so the buffer stays at the native size. Fit scaling
+    /// renders into a buffer as large as the biggest aspect-correct rectangle on the surface.
+    pub fn buffer_size(self, native: (u32, u32), surface: (u32, u32)) -> (u32, u32) {
+        match self {
+            Self::Integer => native,
+            Self::Fit => {
+                let scale = f64::min(
+                    surface.0 as f64 / native.0 as f64,
+                    surface.1 as f64 / native.1 as f64,
+                )
+                .max(1.0);
+
+                (
+                    (native.0 as f64 * scale) as u32,
+                    (native.1 as f64 * scale) as u32,
+                )
+            }
+        }
+    }
+}
+
+/// Scales an RGBA image up to a larger size using nearest-neighbour sampling.
+///
+/// # Panics
+///
+/// If either buffer is smaller than its given dimensions
+pub fn upscale(src: &[u8], src_size: (usize, usize), dst: &mut [u8], dst_size: (usize, usize)) {
+    let (src_width, src_height) = src_size;
+    let (dst_width, dst_height) = dst_size;
+
+    // the source column for each destination column is the same on every row
+    let columns: Vec<usize> = (0..dst_width).map(|x| x * src_width / dst_width).collect();
+
+    for (y, dst_row) in dst
+        .chunks_exact_mut(dst_width * 4)
+        .take(dst_height)
+        .enumerate()
+    {
+        let src_start = (y * src_height / dst_height) * src_width * 4;
+        let src_row = &src[src_start..src_start + src_width * 4];
+
+        for (pixel, &src_x) in dst_row.chunks_exact_mut(4).zip(&columns) {
+            pixel.copy_from_slice(&src_row[src_x * 4..src_x * 4 + 4]);
+        }
+    }
+}
+
+/// Linearly blends between two RGBA colors, where `t` of `0.0` is `from` and `1.0` is `to`
+fn blend(from: u32, to: u32, t: f32) -> [u8; 4] {
+    let from = from.to_be_bytes();
+    let to = to.to_be_bytes();
+    std::array::from_fn(|i| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t) as u8)
+}
+
+/// Emulates the persistence of a CRT's phosphor coating, so that pixels fade out over a few
+/// frames after being turned off instead of disappearing instantly.
+///
+/// CHIP-8 games erase and redraw moving sprites every frame, which flickers badly on a display
+/// that turns pixels off immediately.
+struct Phosphor {
+    /// The brightness of each pixel from `0.0` (off) to `1.0` (fully on)
+    intensity: Vec<f32>,
+    /// How much brightness a pixel that's off loses each frame
+    fade: f32,
+}
+
+impl Phosphor {
+    /// Constructs a phosphor buffer where pixels take `frames` frames to fade out
+    fn new(native_size: (usize, usize), frames: u32) -> Self {
+        Self {
+            intensity: vec![0.0; native_size.0 * native_size.1],
+            fade: 1.0 / frames as f32,
+        }
+    }
+
+    /// Advances the phosphor by one frame, lighting pixels that are on and fading the rest
+    fn update(&mut self, display: &VirtualDisplay) {
+        let width = display.width();
+        for (index, intensity) in self.intensity.iter_mut().enumerate() {
+            if display.get_pixel(index % width, index / width) {
+                *intensity = 1.0;
+            } else {
+                *intensity = (*intensity - self.fade).max(0.0);
+            }
+        }
+    }
+
+    /// Renders the faded display into an RGBA frame at native resolution. Pixels that are on
+    /// right now are always shown at full brightness.
+    fn render_to_buffer(&self, display: &VirtualDisplay, frame: &mut [u8], palette: &Palette) {
+        let width = display.width();
+        let pixels = self.intensity.iter().zip(frame.chunks_exact_mut(4));
+
+        for (index, (intensity, pixel)) in pixels.enumerate() {
+            let intensity = if display.get_pixel(index % width, index / width) {
+                1.0
+            } else {
+                *intensity
+            };
+            pixel.copy_from_slice(&blend(palette.off(), palette.on(), intensity));
+        }
+    }
+}
+
+/// Turns the [`VirtualDisplay`] into RGBA frames for the window.
+///
+/// Rendering happens in three stages, all on the CPU except for integer scaling:
+/// 1. The display is rendered at native resolution, with phosphor persistence if enabled
+/// 2. The post-processing [`Filter`] expands the image
+/// 3. The image is scaled to the window according to the [`ScalingMode`]
+///
+/// Stages that don't change the image are skipped.
+pub struct Renderer {
+    /// The size of the display in CHIP-8 pixels
+    native_size: (usize, usize),
+    /// How the display is scaled to the window
+    scaling: ScalingMode,
+    /// The post-processing filter
+    filter: Filter,
+    /// The size of the frame being rendered into
+    buffer_size: (usize, usize),
+    /// Scratch buffer holding the native resolution frame while it is processed
+    native_frame: Vec<u8>,
+    /// Scratch buffer holding the filtered frame while it is scaled up
+    filtered_frame: Vec<u8>,
+    /// Phosphor persistence, if enabled
+    phosphor: Option<Phosphor>,
+}
+
+impl Renderer {
+    /// Constructs a renderer for the given display.
+    ///
+    /// # Arguments
+    /// * `display` - The display that will be rendered
+    /// * `scaling` - How the display is scaled to the window
+    /// * `filter` - The post-processing filter
+    /// * `persistence` - The number of frames pixels take to fade out, or `0` to disable
+    pub fn new(
+        display: &VirtualDisplay,
+        scaling: ScalingMode,
+        filter: Filter,
+        persistence: u32,
+    ) -> Self {
+        let native_size = (display.width(), display.height());
+        let filtered_len = native_size.0 * native_size.1 * filter.scale().pow(2) * 4;
+
+        Self {
+            native_size,
+            scaling,
+            filter,
+            buffer_size: native_size,
+            native_frame: vec![0; native_size.0 * native_size.1 * 4],
+            filtered_frame: vec![0; filtered_len],
+            phosphor: (persistence > 0).then(|| Phosphor::new(native_size, persistence)),
+        }
+    }
+
+    /// The size of the image after filtering, before it is scaled to the window
+    fn filtered_size(&self) -> (usize, usize) {
+        let scale = self.filter.scale();
+        (self.native_size.0 * scale, self.native_size.1 * scale)
+    }
+
+    /// Returns the size of the frame to render into for a surface of the given size. Call
+    /// [`Self::set_buffer_size`] once the frame has been resized.
+    pub fn buffer_size_for(&self, surface: (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.filtered_size();
+        self.scaling
+            .buffer_size((width as u32, height as u32), surface)
+    }
+
+    /// Sets the size of the frame passed to [`Self::render`]
+    pub fn set_buffer_size(&mut self, size: (u32, u32)) {
+        self.buffer_size = (size.0 as usize, size.1 as usize);
+    }
+
+    /// Advances effects that change over time by one frame. This should be called at 60Hz.
+    pub fn update(&mut self, display: &VirtualDisplay) {
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.update(display);
+        }
+    }
+
+    /// Renders the display into an RGBA frame of the current buffer size
+    pub fn render(&mut self, display: &VirtualDisplay, palette: &Palette, frame: &mut [u8]) {
+        let filtered_size = self.filtered_size();
+        let filtered = self.filter != Filter::None;
+        let scaled = self.buffer_size != filtered_size;
+
+        // each stage writes straight into the frame if it's the last one
+        let native_target = if filtered || scaled {
+            &mut self.native_frame[..]
+        } else {
+            &mut frame[..]
+        };
+
+        match &self.phosphor {
+            Some(phosphor) => phosphor.render_to_buffer(display, native_target, palette),
+            None => display.render_to_buffer(native_target, palette),
+        }
+
+        if filtered {
+            let filter_target = if scaled {
+                &mut self.filtered_frame[..]
+            } else {
+                &mut frame[..]
+            };
+            self.filter
+                .apply(&self.native_frame, self.native_size, filter_target);
+        }
+
+        if scaled {
+            let source = if filtered {
+                &self.filtered_frame
+            } else {
+                &self.native_frame
+            };
+            upscale(source, filtered_size, frame, self.buffer_size);
+        }
+    }
+}
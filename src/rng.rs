@@ -0,0 +1,48 @@
+/// The xorshift64 random number generator used by `Cxkk`.
+///
+/// It's small enough to keep in the [`crate::machine::Machine`], so cloning the machine clones
+/// the generator too, and a forked machine generates the same numbers as the original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Xorshift {
+    /// Never 0, since xorshift would only ever generate 0 from there
+    state: u64,
+}
+
+impl Xorshift {
+    /// Creates a generator from any seed, including 0
+    pub fn new(seed: u64) -> Self {
+        // splitmix64, so that similar seeds give unrelated sequences
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self { state: z.max(1) }
+    }
+
+    /// Restores a generator from [`Self::state`]. Fails if the state is 0.
+    pub fn from_state(state: u64) -> Result<Self, String> {
+        if state == 0 {
+            return Err("Invalid random number generator state: 0".to_string());
+        }
+        Ok(Self { state })
+    }
+
+    /// The internal state, which can be restored with [`Self::from_state`]
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Generates a random byte
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 56) as u8
+    }
+}
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use crate::emulator::TIMER_FREQ;
+
+/// The most frames run at once to catch up after the emulator falls behind. Any further frames
+/// are skipped rather than fast-forwarding through them.
+const MAX_CATCH_UP_FRAMES: u64 = 4;
+
+/// Decides when to run each 60Hz frame of emulation in real time.
+///
+/// Frames are scheduled at fixed times from when the schedule started rather than relative to
+/// when the previous frame actually ran, so small delays in waking up don't add up to drift.
+/// When the emulator falls far behind, such as after the window was blocked or the computer
+/// slept, it runs a few frames to catch up and then starts a new schedule from the present.
+#[derive(Debug)]
+pub struct FrameScheduler {
+    /// When the first frame of the schedule was due
+    start: Instant,
+    /// The number of the next frame to run, counting from [`Self::start`]
+    next_frame: u64,
+}
+
+impl FrameScheduler {
+    /// Constructs a schedule where the first frame is due immediately
+    pub fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            next_frame: 0,
+        }
+    }
+
+    /// Restarts the schedule, with the first frame due immediately
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self::new(now);
+    }
+
+    /// The time at which the given frame of the schedule is due
+    fn frame_time(&self, frame: u64) -> Instant {
+        self.start + Duration::from_nanos(frame * 1_000_000_000 / TIMER_FREQ)
+    }
+
+    /// When the next frame is due. The event loop can sleep until then.
+    pub fn next_deadline(&self) -> Instant {
+        self.frame_time(self.next_frame)
+    }
+
+    /// Returns how many frames should be run now, and marks them as run
+    pub fn due_frames(&mut self, now: Instant) -> u64 {
+        if now < self.next_deadline() {
+            return 0;
+        }
+
+        // the number of frames whose `frame_time` has passed, rounding the same way it does so
+        // that a frame is due exactly at its deadline
+        let elapsed = now.duration_since(self.start).as_nanos() as u64;
+        let due = ((elapsed + 1) * TIMER_FREQ - 1) / 1_000_000_000 + 1 - self.next_frame;
+
+        if due > MAX_CATCH_UP_FRAMES {
+            log::debug!(
+                "Emulation fell {} frames behind, skipping {} of them",
+                due,
+                due - MAX_CATCH_UP_FRAMES
+            );
+            // the next frame is due a whole frame from now
+            self.start = now;
+            self.next_frame = 1;
+            return MAX_CATCH_UP_FRAMES;
+        }
+
+        self.next_frame += due;
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How long after the start of the schedule the given frame is due
+    fn after(frames: u64) -> Duration {
+        Duration::from_nanos(frames * 1_000_000_000 / TIMER_FREQ)
+    }
+
+    #[test]
+    fn frames_on_time_run_one_at_a_time() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(start);
+        assert_eq!(scheduler.due_frames(start), 1);
+        assert_eq!(scheduler.due_frames(start + Duration::from_millis(1)), 0);
+
+        for frame in 1..=120 {
+            assert_eq!(scheduler.next_deadline(), start + after(frame));
+            let early = start + after(frame) - Duration::from_nanos(1);
+            assert_eq!(scheduler.due_frames(early), 0);
+            assert_eq!(scheduler.due_frames(start + after(frame)), 1);
+        }
+    }
+
+    #[test]
+    fn small_delays_catch_up_without_drifting() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(start);
+        scheduler.due_frames(start);
+
+        // waking up late doesn't push back the next deadline
+        let late = Duration::from_millis(5);
+        assert_eq!(scheduler.due_frames(start + after(1) + late), 1);
+        assert_eq!(scheduler.next_deadline(), start + after(2));
+
+        // missing a couple of frames runs them all at once
+        assert_eq!(scheduler.due_frames(start + after(4) + late), 3);
+        assert_eq!(scheduler.next_deadline(), start + after(5));
+
+        for frame in 5..=60 {
+            assert_eq!(scheduler.due_frames(start + after(frame) + late), 1);
+        }
+        assert_eq!(
+            scheduler.next_deadline(),
+            start + Duration::from_secs(1) + after(1)
+        );
+    }
+
+    #[test]
+    fn long_stalls_start_a_new_schedule() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(start);
+        scheduler.due_frames(start);
+
+        let resumed = start + Duration::from_secs(10);
+        assert_eq!(scheduler.due_frames(resumed), MAX_CATCH_UP_FRAMES);
+        assert_eq!(scheduler.next_deadline(), resumed + after(1));
+        assert_eq!(scheduler.due_frames(resumed + Duration::from_millis(1)), 0);
+        assert_eq!(scheduler.due_frames(resumed + after(1)), 1);
+        assert_eq!(scheduler.due_frames(resumed + after(2)), 1);
+    }
+}
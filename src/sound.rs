@@ -1,27 +1,105 @@
-use std::{fmt::Debug, time::Duration};
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use rodio::{source::SineWave, OutputStream, OutputStreamBuilder, Sink, Source};
+use rodio::{ChannelCount, OutputStream, OutputStreamBuilder, SampleRate, Sink, Source};
+
+use crate::{
+    audio::AudioSink,
+    tone::{SAMPLE_RATE, SAMPLES_PER_FRAME},
+};
+
+/// Samples to buffer before playback starts, absorbing jitter in when frames are generated
+const PREBUFFER_SAMPLES: usize = SAMPLES_PER_FRAME * 2;
+/// The most samples that can be queued before the oldest are dropped, which bounds the latency
+/// when the emulator produces audio faster than real time
+const MAX_QUEUED_SAMPLES: usize = SAMPLES_PER_FRAME * 6;
+
+/// An endless [`Source`] playing samples queued by the [`Speaker`], or silence when there are
+/// none
+struct QueuedSamples {
+    /// Samples shared with the [`Speaker`]
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    /// Samples taken from the queue that are waiting to be played. Taking them in batches avoids
+    /// locking the queue for every sample.
+    pending: VecDeque<f32>,
+    /// Whether playback has run dry and is waiting for [`PREBUFFER_SAMPLES`] to be queued
+    buffering: bool,
+}
+
+impl Iterator for QueuedSamples {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty()
+            && let Ok(mut queue) = self.queue.lock()
+        {
+            if self.buffering && queue.len() >= PREBUFFER_SAMPLES {
+                self.buffering = false;
+            }
+
+            if !self.buffering {
+                std::mem::swap(&mut self.pending, &mut queue);
+            }
+        }
+
+        match self.pending.pop_front() {
+            Some(sample) => Some(sample),
+            None => {
+                // ran dry, so wait for a few frames to queue up before resuming
+                self.buffering = true;
+                Some(0.0)
+            }
+        }
+    }
+}
+
+impl Source for QueuedSamples {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        1
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
 
 /// Cross-platform audio wrapper for CHIP-8 beeps
+///
+/// The speaker doesn't generate sound itself, it plays samples generated by the emulator as it
+/// runs. Audio therefore follows emulated time, and goes quiet whenever the emulator stops.
 pub struct Speaker {
-    /// This must be held as long as [`Self::sink`] lives
+    /// This must be held as long as [`Self::_sink`] lives
     _stream: OutputStream,
     /// The audio stream used for playing beeps
-    sink: Sink,
-    /// Whether or not the stream is currently playing
-    is_playing: bool,
+    _sink: Sink,
+    /// Samples waiting to be played
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    /// Whether or not the beep is muted
+    muted: bool,
 }
 
 impl Debug for Speaker {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Speaker")
-            .field("is_playing", &self.is_playing)
+            .field("muted", &self.muted)
             .finish()
     }
 }
 
 impl Speaker {
-    /// Attempts to create a new sink with a preloaded 1000 second long sine wave (440Hz)
+    /// Attempts to create a new sink that plays queued samples
     pub fn new() -> Option<Self> {
         match OutputStreamBuilder::open_default_stream() {
             Ok(mut stream_handle) => {
@@ -32,17 +110,18 @@ impl Speaker {
 
                 let sink = Sink::connect_new(stream_handle.mixer());
 
-                // creates a new 1000 second long A note and attaches it to the sink
-                let source = SineWave::new(440.0)
-                    .take_duration(Duration::from_secs(1000))
-                    .amplify(0.20);
-                sink.append(source);
-                sink.pause();
+                let queue = Arc::new(Mutex::new(VecDeque::new()));
+                sink.append(QueuedSamples {
+                    queue: Arc::clone(&queue),
+                    pending: VecDeque::new(),
+                    buffering: true,
+                });
 
                 Some(Self {
                     _stream: stream_handle,
-                    sink,
-                    is_playing: false,
+                    _sink: sink,
+                    queue,
+                    muted: false,
                 })
             }
             Err(e) => {
@@ -51,23 +130,36 @@ impl Speaker {
             }
         }
     }
+}
+
+impl AudioSink for Speaker {
+    /// Queues samples to be played after those already queued. Silence is queued instead while
+    /// muted.
+    fn write_frame(&mut self, _beeping: bool, samples: &[f32]) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.extend(
+                samples
+                    .iter()
+                    .map(|&sample| if self.muted { 0.0 } else { sample }),
+            );
 
-    /// Starts a beep's playing
-    pub fn start(&mut self) {
-        log::debug!("Starting sound");
-        self.sink.play();
-        self.is_playing = true;
+            if queue.len() > MAX_QUEUED_SAMPLES {
+                let excess = queue.len() - MAX_QUEUED_SAMPLES;
+                log::trace!("Audio is falling behind, dropping {} samples", excess);
+                queue.drain(..excess);
+            }
+        }
     }
 
-    /// Stops a beep's playing
-    pub fn stop(&mut self) {
-        log::debug!("Stopping sound");
-        self.sink.pause();
-        self.is_playing = false;
+    /// Discards any samples that haven't been played yet
+    fn clear(&mut self) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.clear();
+        }
     }
 
-    /// Whether or not the beep is currently playing
-    pub fn is_playing(&self) -> bool {
-        self.is_playing
+    /// Mutes or unmutes the beep
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
     }
 }
@@ -1,5 +1,7 @@
+use std::hash::{Hash, Hasher};
+
 /// Represents the 16-layer CHIP-8 stack
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Stack {
     /// CHIP-8 spec requires a stack that goes 16 levels deep
     memory: [u16; 16],
@@ -16,16 +18,39 @@ impl Stack {
         }
     }
 
-    /// Push an item to the stack and increment the stack pointer
-    pub fn push(&mut self, value: u16) {
-        self.memory[self.stack_pointer as usize] = value;
+    /// Push an item to the stack and increment the stack pointer. Fails if the stack is full.
+    pub fn push(&mut self, value: u16) -> Result<(), String> {
+        let slot = self
+            .memory
+            .get_mut(self.stack_pointer as usize)
+            .ok_or_else(|| "Stack overflow".to_string())?;
+        *slot = value;
         self.stack_pointer += 1;
+        Ok(())
+    }
+
+    /// Constructs a stack holding the given items, from the bottom up. Fails if there are more
+    /// than 16.
+    pub fn from_slice(items: &[u16]) -> Result<Self, String> {
+        let mut stack = Self::new();
+        for &item in items {
+            stack.push(item)?;
+        }
+        Ok(stack)
     }
 
-    /// Pop an item from the stack and decrement the stack pointer
-    pub fn pop(&mut self) -> u16 {
-        self.stack_pointer -= 1;
-        self.memory[self.stack_pointer as usize]
+    /// Returns the items on the stack, from the bottom up
+    pub fn as_slice(&self) -> &[u16] {
+        &self.memory[..self.stack_pointer as usize]
+    }
+
+    /// Pop an item from the stack and decrement the stack pointer. Fails if the stack is empty.
+    pub fn pop(&mut self) -> Result<u16, String> {
+        self.stack_pointer = self
+            .stack_pointer
+            .checked_sub(1)
+            .ok_or_else(|| "Stack underflow".to_string())?;
+        Ok(self.memory[self.stack_pointer as usize])
     }
 }
 
@@ -34,3 +59,19 @@ impl Default for Stack {
         Self::new()
     }
 }
+
+// popped items are left behind in memory, so only the items on the stack are compared
+
+impl PartialEq for Stack {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Stack {}
+
+impl Hash for Stack {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state);
+    }
+}
@@ -0,0 +1,186 @@
+use std::f32::consts::TAU;
+
+use serde::Deserialize;
+
+/// The sample rate that tones are generated at
+pub const SAMPLE_RATE: u32 = 44_100;
+/// The number of samples generated per 60Hz timer tick
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / 60) as usize;
+/// How long it takes the beep to fade in or out, which avoids clicks when it starts and stops
+const RAMP_SECONDS: f32 = 0.005;
+
+/// The shape of the beep's waveform
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Waveform {
+    /// A buzzy square wave, the closest to what real hardware sounded like
+    Square,
+    /// A pure sine wave
+    #[default]
+    Sine,
+    /// A softer triangle wave
+    Triangle,
+    /// A harsh sawtooth wave
+    Sawtooth,
+    /// White noise, resampled at the tone's frequency
+    Noise,
+}
+
+/// The settings of the beep played while the sound timer is active
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    /// The pitch in Hz
+    pub frequency: f32,
+    /// The shape of the waveform
+    pub waveform: Waveform,
+    /// The volume from `0.0` (silent) to `1.0` (full scale)
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self {
+            frequency: 440.0,
+            waveform: Waveform::default(),
+            volume: 0.20,
+        }
+    }
+}
+
+/// Checks a pitch in Hz is finite and above 0, since the waveform would never complete a cycle
+/// otherwise
+pub fn check_frequency(frequency: f32) -> Result<f32, String> {
+    if frequency.is_finite() && frequency > 0.0 {
+        Ok(frequency)
+    } else {
+        Err(format!(
+            "Frequency {} is not a positive number of Hz",
+            frequency
+        ))
+    }
+}
+
+/// Checks a volume is a number. Volumes outside of `0.0` to `1.0` are clamped by
+/// [`Oscillator::new`] rather than rejected.
+pub fn check_volume(volume: f32) -> Result<f32, String> {
+    if volume.is_nan() {
+        Err("Volume is not a number".to_string())
+    } else {
+        Ok(volume)
+    }
+}
+
+/// Parses a pitch in Hz, see [`check_frequency`]
+pub fn parse_frequency(s: &str) -> Result<f32, String> {
+    let frequency = s
+        .parse()
+        .map_err(|e| format!("`{}` is not a frequency: {}", s, e))?;
+    check_frequency(frequency)
+}
+
+/// Parses a volume, see [`check_volume`]
+pub fn parse_volume(s: &str) -> Result<f32, String> {
+    let volume = s
+        .parse()
+        .map_err(|e| format!("`{}` is not a volume: {}", s, e))?;
+    check_volume(volume)
+}
+
+/// Generates the samples of a [`Tone`] that can be gated on and off.
+///
+/// The tone fades in and out over a few milliseconds whenever the gate changes rather than
+/// jumping straight to full volume or silence.
+#[derive(Debug, Clone)]
+pub struct Oscillator {
+    /// The tone being generated
+    tone: Tone,
+    /// Position within the current cycle of the waveform, from `0.0` to `1.0`
+    phase: f32,
+    /// The current volume envelope, from `0.0` to `1.0`
+    gain: f32,
+    /// Whether the tone should currently be sounding
+    gate: bool,
+    /// State of the noise generator
+    noise_state: u32,
+    /// The noise value held until the next cycle of the waveform
+    noise_sample: f32,
+}
+
+impl Oscillator {
+    /// Constructs a silent oscillator for the given tone. An invalid frequency or volume is
+    /// replaced with the default one, and volumes outside of `0.0` to `1.0` are clamped.
+    pub fn new(mut tone: Tone) -> Self {
+        let default = Tone::default();
+        if let Err(e) = check_frequency(tone.frequency) {
+            log::warn!("{}, using {}Hz", e, default.frequency);
+            tone.frequency = default.frequency;
+        }
+        if let Err(e) = check_volume(tone.volume) {
+            log::warn!("{}, using {}", e, default.volume);
+            tone.volume = default.volume;
+        }
+        tone.volume = tone.volume.clamp(0.0, 1.0);
+
+        Self {
+            tone,
+            phase: 0.0,
+            gain: 0.0,
+            gate: false,
+            noise_state: 0x1234_5678,
+            noise_sample: 0.0,
+        }
+    }
+
+    /// Sets whether the tone should be sounding. It fades in or out from the next sample on.
+    pub fn set_gate(&mut self, gate: bool) {
+        self.gate = gate;
+    }
+
+    /// Returns the next noise value between `-1.0` and `1.0`
+    fn next_noise(&mut self) -> f32 {
+        // xorshift32
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        self.noise_state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Generates the next sample
+    pub fn next_sample(&mut self) -> f32 {
+        let ramp_step = 1.0 / (RAMP_SECONDS * SAMPLE_RATE as f32);
+        let target = if self.gate { 1.0 } else { 0.0 };
+        if self.gain < target {
+            self.gain = (self.gain + ramp_step).min(target);
+        } else {
+            self.gain = (self.gain - ramp_step).max(target);
+        }
+
+        // keep the waveform still while silent so the tone always starts from the same point
+        if self.gain == 0.0 {
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let value = match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (self.phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            Waveform::Noise => self.noise_sample,
+        };
+
+        self.phase += self.tone.frequency / SAMPLE_RATE as f32;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            self.noise_sample = self.next_noise();
+        }
+
+        value * self.gain * self.tone.volume
+    }
+}
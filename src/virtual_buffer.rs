@@ -1,89 +1,123 @@
 use std::fmt::Debug;
 
+use crate::palette::Palette;
+
 /// The screen width that we're emulating
 const VIRTUAL_WIDTH: usize = 64;
 /// The screen height that we're emulating
 const VIRTUAL_HEIGHT: usize = 32;
 
-/// The RGBA value of a pixel being on
-const PIXEL_ON: u32 = 0xFFFFFFFF;
-/// The RGBA value of a pixel being off
-const PIXEL_OFF: u32 = 0x1A1A1AFF;
-
-/// A virtual display for rendering CHIP-8 graphics at a scaled resolution
+/// A virtual display for rendering CHIP-8 graphics
 ///
-/// This represents a simple boolean pixel buffer where pixels can either be on or off. It acts as
-/// a smaller screen, and can upscale to the specified factor.
+/// This represents a simple pixel buffer where pixels can either be on or off, stored at the
+/// native CHIP-8 resolution as a bitset with one [`u64`] per row. Scaling up to the window size is
+/// left to the renderer.
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct VirtualDisplay {
-    /// The internal boolean pixel buffer. Stored as a 1D array
-    buffer: Vec<bool>,
-    /// The scaled up width in pixels of the display buffer
-    scaled_width: usize,
-    /// The scaled up height in pixels of the display buffer
-    scaled_height: usize,
-    /// The scaling factor used to convert virtual pixels to real pixels
-    scale_factor: usize,
+    /// The rows of the display. The most significant bit of each row is the leftmost pixel.
+    rows: [u64; VIRTUAL_HEIGHT],
 }
 
 impl VirtualDisplay {
-    /// Construct a new [`VirtualDisplay`] with a given scale factor.
-    ///
-    /// The total buffer dimentions are determined by the virtual size multiplied by the scale
-    /// factor.
-    ///
-    /// # Arguments
-    /// * `scale_factor` - The number of real pixels per virtual pixel
+    /// Construct a new, blank [`VirtualDisplay`]
     ///
     /// # Example
     /// ```
-    /// let display = VirtualDisplay::new(10);
-    /// // given a 64x32 virtual size
-    /// assert_eq!(display.scaled_width(), 640);
+    /// # use chip8_rs::virtual_buffer::VirtualDisplay;
+    /// let display = VirtualDisplay::new();
+    /// assert_eq!(display.width(), 64);
     /// ```
-    pub fn new(scale_factor: usize) -> Self {
+    pub fn new() -> Self {
         Self {
-            buffer: vec![false; (VIRTUAL_WIDTH * scale_factor) * (VIRTUAL_HEIGHT * scale_factor)],
-            scaled_width: VIRTUAL_WIDTH * scale_factor,
-            scaled_height: VIRTUAL_HEIGHT * scale_factor,
-            scale_factor,
+            rows: [0; VIRTUAL_HEIGHT],
         }
     }
 
-    /// Returnes the scaled width in pixels
-    pub const fn scaled_width(&self) -> usize {
-        self.scaled_width
+    /// Returns the virtual width in pixels
+    pub const fn width(&self) -> usize {
+        VIRTUAL_WIDTH
     }
 
-    /// Returnes the scaled height in pixels
-    pub const fn scaled_height(&self) -> usize {
-        self.scaled_height
+    /// Returns the virtual height in pixels
+    pub const fn height(&self) -> usize {
+        VIRTUAL_HEIGHT
     }
 
     /// Clears the entire display by turning off all pixels
     pub fn clear(&mut self) {
         log::trace!("Clearing display");
-        self.buffer.fill(false);
+        self.rows.fill(0);
     }
 
-    /// Renders the internal buffer into a given RGBA byte frame.
+    /// Renders the display into a given RGBA byte frame at native resolution.
     ///
-    /// Each pixel is expanded into four bytes. [`PIXEL_ON`] and [`PIXEL_OFF`] define the colors
-    /// for on and off pixels repectively.
+    /// Each pixel is expanded into four bytes, colored with the on and off colors of the given
+    /// palette. The frame must be [`Self::width`] by [`Self::height`] pixels.
     ///
     /// # Arguments
     ///
     /// * `frame` - A mutable slice of bytes where the RGBA data should be written
+    /// * `palette` - The colors to render pixels with
     ///
     /// # Panics
     ///
     /// If the provided frame is not large enough to hold the display data
-    pub fn render_to_buffer(&self, frame: &mut [u8]) {
-        for (index, pixel_on) in self.buffer.iter().enumerate() {
-            let rgba = if *pixel_on { PIXEL_ON } else { PIXEL_OFF };
+    pub fn render_to_buffer(&self, frame: &mut [u8], palette: &Palette) {
+        let on = palette.on().to_be_bytes();
+        let off = palette.off().to_be_bytes();
+
+        for (row, frame_row) in self
+            .rows
+            .iter()
+            .zip(frame.chunks_exact_mut(VIRTUAL_WIDTH * 4))
+        {
+            for (x, pixel) in frame_row.chunks_exact_mut(4).enumerate() {
+                let rgba = if row & Self::column_mask(x) != 0 {
+                    on
+                } else {
+                    off
+                };
+                pixel.copy_from_slice(&rgba);
+            }
+        }
+    }
+
+    /// Returns the bit of a row that represents the pixel in column `x`
+    const fn column_mask(x: usize) -> u64 {
+        1 << (VIRTUAL_WIDTH - 1 - x)
+    }
+
+    /// Returns the rows of the display as a bitset, where the most significant bit of each row
+    /// is the leftmost pixel
+    pub fn rows(&self) -> &[u64] {
+        &self.rows
+    }
+
+    /// Returns each row of the display as 16 hex digits, where the most significant bit is the
+    /// leftmost pixel. This is how the display is exchanged with external tools.
+    pub fn to_hex_rows(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|row| format!("{:016x}", row))
+            .collect()
+    }
 
-            let start = index * 4;
-            frame[start..start + 4].copy_from_slice(&rgba.to_be_bytes());
+    /// Constructs a display from rows of hex digits, as returned by [`Self::to_hex_rows`]
+    pub fn from_hex_rows(rows: &[String]) -> Result<Self, String> {
+        if rows.len() != VIRTUAL_HEIGHT {
+            return Err(format!(
+                "Expected {} display rows, got {}",
+                VIRTUAL_HEIGHT,
+                rows.len()
+            ));
         }
+
+        let mut display = Self::new();
+        for (row, hex) in display.rows.iter_mut().zip(rows) {
+            *row = u64::from_str_radix(hex, 16)
+                .map_err(|e| format!("Invalid display row `{}`: {}", hex, e))?;
+        }
+        Ok(display)
     }
 
     /// Returns the state of a virtual pixel at the given coordinates.
@@ -93,17 +127,13 @@ impl VirtualDisplay {
         x %= VIRTUAL_WIDTH;
         y %= VIRTUAL_HEIGHT;
 
-        let real_x = x * self.scale_factor;
-        let real_y = y * self.scale_factor;
-        let real_index = real_y * self.scaled_width + real_x;
-
-        self.buffer[real_index]
+        self.rows[y] & Self::column_mask(x) != 0
     }
 
     /// Sets a virtual pixel at the given coordinates to the given state.
     ///
-    /// Each virtual pixel affects a `scale_factor * scale_factor` block of real pixels.
-    /// Pixels are XORed with the new state to allow for sprite drawing behavior.
+    /// Pixels are XORed with the new state to allow for sprite drawing behavior. Coordinates
+    /// automatically wrap if they overflow.
     ///
     /// Returns `true` if setting the pixel caused a collision
     pub fn set_pixel(&mut self, mut x: usize, mut y: usize, state: bool) -> bool {
@@ -112,27 +142,32 @@ impl VirtualDisplay {
         x %= VIRTUAL_WIDTH;
         y %= VIRTUAL_HEIGHT;
 
-        let start_x = x * self.scale_factor;
-        let start_y = y * self.scale_factor;
-        let end_x = (x + 1) * self.scale_factor;
-        let end_y = (y + 1) * self.scale_factor;
+        if state {
+            self.rows[y] ^= Self::column_mask(x);
+        }
 
-        for y in start_y..end_y {
-            for x in start_x..end_x {
-                let index = y * self.scaled_width + x;
-                if let Some(pixel) = self.buffer.get_mut(index) {
-                    *pixel ^= state;
+        collision
+    }
+
+    /// Draws a text bitmap on the display with its top-left corner at `(x, y)`.
+    ///
+    /// Each string is one row, where `#` turns a pixel on and any other character leaves it
+    /// untouched. This is used for frontend messages rather than emulated drawing, so collisions
+    /// are not reported.
+    pub fn draw_bitmap(&mut self, x: usize, y: usize, rows: &[&str]) {
+        for (row_index, row) in rows.iter().enumerate() {
+            for (col_index, ch) in row.chars().enumerate() {
+                if ch == '#' {
+                    self.set_pixel(x + col_index, y + row_index, true);
                 }
             }
         }
-
-        collision
     }
 
     /// Draws a sprite on the display at `(x, y)` using the provided bytes of pixel data.
     ///
-    /// Each byte in `pixels` represents one row of 8 bits. Drawing wraps around the screen
-    /// edges.
+    /// Each byte in `pixels` represents one row of 8 bits. The starting coordinates always wrap,
+    /// while the parts of the sprite that go past the screen edges are either wrapped or clipped.
     ///
     /// Returns `true` if any pixel collisions occurred during drawing
     ///
@@ -150,66 +185,47 @@ impl VirtualDisplay {
         pixels: &[u8],
         clip_sprite: bool,
     ) -> bool {
+        let x = (x % VIRTUAL_WIDTH) as u32;
+        let y = y % VIRTUAL_HEIGHT;
         let mut collision = false;
 
-        for (row_index, row) in pixels.iter().enumerate().take(num_rows) {
-            let coord_y = y + row_index;
-
-            for bit in 0..8 {
-                let coord_x = x + bit;
-
-                let value = row & (1 << (7 - bit));
-                if value > 0 {
-                    collision |= self.set_pixel(coord_x, coord_y, true);
-                }
-
-                if clip_sprite && coord_x == VIRTUAL_WIDTH - 1 {
+        for (row_index, &row) in pixels.iter().enumerate().take(num_rows) {
+            let mut coord_y = y + row_index;
+            if coord_y >= VIRTUAL_HEIGHT {
+                if clip_sprite {
                     break;
                 }
+                coord_y %= VIRTUAL_HEIGHT;
             }
 
-            if clip_sprite && coord_y == VIRTUAL_HEIGHT - 1 {
-                break;
-            }
+            // move the sprite row to the leftmost byte, then over to its column. Shifting drops
+            // the bits past the right edge, while rotating wraps them around to the left
+            let sprite_row = (row as u64) << (VIRTUAL_WIDTH - 8);
+            let sprite_row = if clip_sprite {
+                sprite_row >> x
+            } else {
+                sprite_row.rotate_right(x)
+            };
+
+            collision |= self.rows[coord_y] & sprite_row != 0;
+            self.rows[coord_y] ^= sprite_row;
         }
 
         collision
     }
 }
 
+impl Default for VirtualDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Debug for VirtualDisplay {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VirtualDisplay")
-            .field("scaled_width", &self.scaled_width)
-            .field("scaled_height", &self.scaled_height)
-            .field("scale_factor", &self.scale_factor)
+            .field("width", &VIRTUAL_WIDTH)
+            .field("height", &VIRTUAL_HEIGHT)
             .finish()
     }
 }
-
-impl<'a> IntoIterator for &'a VirtualDisplay {
-    type Item = &'a bool;
-    type IntoIter = std::slice::Iter<'a, bool>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.buffer.iter()
-    }
-}
-
-impl<'a> IntoIterator for &'a mut VirtualDisplay {
-    type Item = &'a mut bool;
-    type IntoIter = std::slice::IterMut<'a, bool>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.buffer.iter_mut()
-    }
-}
-
-impl IntoIterator for VirtualDisplay {
-    type Item = bool;
-    type IntoIter = std::vec::IntoIter<bool>;
-
-    fn into_iter(self) -> Self::IntoIter {
-        self.buffer.into_iter()
-    }
-}
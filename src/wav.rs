@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{audio::AudioSink, tone::SAMPLE_RATE};
+
+/// The size of the RIFF and format headers before the sample data
+const HEADER_SIZE: u32 = 44;
+/// The most samples a WAV file can hold, since the size of the file after the first 8 bytes has
+/// to fit in 32 bits
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / 2;
+
+/// Writes mono 16-bit PCM samples to a WAV file.
+///
+/// The sizes in the header are only filled in by [`Self::finish`], which is also attempted when
+/// the writer is dropped. Recordings are limited to [`MAX_SAMPLES`], about 13 hours, by the
+/// format's 4 GiB limit.
+#[derive(Debug)]
+pub struct WavWriter {
+    /// The file being written. `None` once the file has been finished.
+    file: Option<BufWriter<File>>,
+    /// The number of samples written so far
+    samples: u32,
+}
+
+impl WavWriter {
+    /// Creates a WAV file at the given path, overwriting any existing file
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        log::info!("Recording audio to: {}", path.display());
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+
+        file.write_all(b"RIFF")?;
+        // the file and data sizes are placeholders until the file is finished
+        file.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&bits_per_sample.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            file: Some(file),
+            samples: 0,
+        })
+    }
+
+    /// Appends samples in the range `-1.0..=1.0` to the file. Fails once the file is full, after
+    /// writing the samples that fit.
+    pub fn write(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let room = MAX_SAMPLES.saturating_sub(self.samples) as usize;
+        let (fits, rest) = samples.split_at(samples.len().min(room));
+        for sample in fits {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            file.write_all(&value.to_le_bytes())?;
+        }
+        // can't overflow since no more than `room` samples were written
+        self.samples += fits.len() as u32;
+
+        if !rest.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::FileTooLarge,
+                "WAV files can't be larger than 4 GiB",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fills in the header sizes and flushes the file. Further writes are ignored.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+
+        let data_size = self.samples.checked_mul(2).and_then(|size| {
+            size.checked_add(HEADER_SIZE - 8)
+                .map(|riff_size| (size, riff_size))
+        });
+        let Some((data_size, riff_size)) = data_size else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::FileTooLarge,
+                "WAV files can't be larger than 4 GiB",
+            ));
+        };
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&riff_size.to_le_bytes())?;
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        file.write_all(&data_size.to_le_bytes())?;
+        file.flush()
+    }
+}
+
+impl AudioSink for WavWriter {
+    /// Appends the samples to the file. Recording stops if writing fails or the file is full,
+    /// keeping what was written so far.
+    fn write_frame(&mut self, _beeping: bool, samples: &[f32]) {
+        if let Err(e) = self.write(samples) {
+            log::error!("Error writing audio recording, stopping: {}", e);
+            if let Err(e) = self.finish() {
+                log::error!("Error finishing WAV file: {:?}", e);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        WavWriter::finish(self)
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::error!("Error finishing WAV file: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn full_files_keep_a_valid_header() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join(format!("chip8-rs-wav-{}.wav", std::process::id()));
+        let mut writer = WavWriter::create(&path)?;
+        // pretend the file is almost full rather than writing 4 GiB
+        writer.samples = MAX_SAMPLES - 2;
+
+        assert!(writer.write(&[0.5; 4]).is_err());
+        assert_eq!(writer.samples, MAX_SAMPLES);
+        assert!(writer.write(&[0.5]).is_err());
+        assert!(writer.finish().is_ok());
+
+        let mut header = [0; HEADER_SIZE as usize];
+        let read = File::open(&path).and_then(|mut file| file.read_exact(&mut header));
+        std::fs::remove_file(&path)?;
+        read?;
+
+        let field = |offset: usize| u32::from_le_bytes([0, 1, 2, 3].map(|i| header[offset + i]));
+        assert_eq!(field(40), MAX_SAMPLES * 2);
+        assert_eq!(field(4), MAX_SAMPLES * 2 + (HEADER_SIZE - 8));
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+//! Tests for the sound the emulator produces
+
+use chip8_rs::{
+    audio::{AudioRecorder, NullSink},
+    emulator::Chip8,
+    tone::{SAMPLES_PER_FRAME, Tone, Waveform},
+};
+
+/// Sets the sound timer to `frames` and halts
+fn beep(frames: u8) -> [u8; 6] {
+    [0x60, frames, 0xF0, 0x18, 0x12, 0x04]
+}
+
+#[test]
+fn beeps_last_as_long_as_the_sound_timer() {
+    for frames in [1, 2, 5, 60, 255] {
+        let recorder = AudioRecorder::new();
+        let mut emulator = Chip8::new();
+        emulator.add_audio_sink(Box::new(recorder.clone()));
+        emulator.load(&beep(frames));
+        for _ in 0..300 {
+            emulator.run_frame();
+        }
+
+        let recording = recorder.recording();
+        assert_eq!(recording.beeping.len(), 300);
+        let beeping: Vec<_> = recording
+            .beeping
+            .iter()
+            .take_while(|&&beeping| beeping)
+            .collect();
+        assert_eq!(
+            beeping.len(),
+            frames as usize,
+            "sound timer set to {}",
+            frames
+        );
+        assert_eq!(
+            recording.beeping.iter().filter(|&&beeping| beeping).count(),
+            frames as usize
+        );
+        assert_eq!(recording.samples.len(), 300 * SAMPLES_PER_FRAME);
+    }
+}
+
+#[test]
+fn silence_produces_no_sound() {
+    let recorder = AudioRecorder::new();
+    let mut emulator = Chip8::new();
+    emulator.add_audio_sink(Box::new(NullSink));
+    emulator.add_audio_sink(Box::new(recorder.clone()));
+    emulator.load(&beep(0));
+    for _ in 0..10 {
+        emulator.run_frame();
+    }
+
+    let recording = recorder.recording();
+    assert!(recording.beeping.iter().all(|&beeping| !beeping));
+    assert!(recording.samples.iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn beeps_are_audible_but_recordings_ignore_muting() {
+    let recorder = AudioRecorder::new();
+    let mut emulator = Chip8::new();
+    emulator.add_audio_sink(Box::new(recorder.clone()));
+    emulator.toggle_mute();
+    emulator.load(&beep(10));
+    for _ in 0..10 {
+        emulator.run_frame();
+    }
+
+    let recording = recorder.recording();
+    assert!(recording.samples.iter().any(|&sample| sample != 0.0));
+    assert!(
+        recording
+            .samples
+            .iter()
+            .all(|sample| (-1.0..=1.0).contains(sample))
+    );
+}
+
+#[test]
+fn loud_tones_are_clamped() {
+    for waveform in [Waveform::Square, Waveform::Sine, Waveform::Noise] {
+        let recorder = AudioRecorder::new();
+        let tone = Tone {
+            volume: 4.0,
+            waveform,
+            ..Tone::default()
+        };
+        let mut emulator = Chip8::with_tone(tone);
+        emulator.add_audio_sink(Box::new(recorder.clone()));
+        emulator.load(&beep(10));
+        for _ in 0..10 {
+            emulator.run_frame();
+        }
+
+        let recording = recorder.recording();
+        assert!(recording.samples.iter().any(|&sample| sample.abs() > 0.9));
+        assert!(
+            recording
+                .samples
+                .iter()
+                .all(|sample| (-1.0..=1.0).contains(sample))
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! Conformance tests. Test programs are run headlessly under every platform profile for a
+//! fixed number of frames, and the final framebuffer is compared against a golden image in
+//! `tests/golden`. The golden images only catch changes in behaviour, so the registers and
+//! pixels each program should end up with are also checked against values worked out by hand
+//! from the specification.
+//!
+//! The golden images are plain text, one line per row with `#` for a lit pixel. To regenerate
+//! them after an intentional change in behaviour, run the tests with `UPDATE_GOLDEN=1` and
+//! review the diff.
+
+use std::{fs, path::PathBuf};
+
+use chip8_rs::{
+    emulator::{Chip8, Quirks},
+    instruction::Instruction::{self, *},
+};
+
+/// Where programs are loaded
+const PROGRAM_START: u16 = 0x200;
+/// Where the register dump routine is placed. Test code must end before it.
+const DUMP: u16 = 0x280;
+/// Where the dump routine halts once it has drawn the registers
+const HALT: u16 = DUMP + 18;
+/// An 8x8 block sprite, followed by a single pixel sprite
+const SPRITES: u16 = 0x2C0;
+/// Scratch memory for the tests
+const SCRATCH: u16 = 0x300;
+/// Where the dump routine stores the registers
+const DUMP_BUFFER: u16 = 0x340;
+
+/// The number of frames test-suite ROMs are run for
+const ROM_FRAMES: u64 = 600;
+
+/// Builds a test program from instructions
+struct Assembler {
+    code: Vec<u8>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self { code: Vec::new() }
+    }
+
+    /// The address of the next instruction
+    fn here(&self) -> u16 {
+        PROGRAM_START + self.code.len() as u16
+    }
+
+    fn op(&mut self, instruction: Instruction) -> &mut Self {
+        self.code
+            .extend_from_slice(&instruction.encode().to_be_bytes());
+        self
+    }
+
+    /// Ends the test by jumping to the register dump
+    fn dump(&mut self) -> &mut Self {
+        self.op(Jump { addr: DUMP })
+    }
+
+    /// Lays out the program, the dump routine, and the sprite data as a ROM
+    fn finish(mut self) -> Vec<u8> {
+        assert!(
+            self.here() <= DUMP,
+            "test program overlaps the dump routine"
+        );
+        self.code.resize((DUMP - PROGRAM_START) as usize, 0);
+
+        // store every register and draw them as two 8-row sprites in the middle of the
+        // display, one row per register from v0 to vF
+        self.op(LoadIndex { addr: DUMP_BUFFER })
+            .op(StoreRegisters { x: 0xF })
+            .op(Load { x: 0, value: 24 })
+            .op(Load { x: 1, value: 32 })
+            .op(Load { x: 2, value: 12 })
+            .op(LoadIndex { addr: DUMP_BUFFER })
+            .op(Draw {
+                x: 0,
+                y: 2,
+                rows: 8,
+            })
+            .op(LoadIndex {
+                addr: DUMP_BUFFER + 8,
+            })
+            .op(Draw {
+                x: 1,
+                y: 2,
+                rows: 8,
+            });
+        assert_eq!(self.here(), HALT, "the dump routine should halt at HALT");
+        self.op(Jump { addr: HALT });
+
+        assert!(self.here() <= SPRITES, "dump routine overlaps the sprites");
+        self.code.resize((SPRITES - PROGRAM_START) as usize, 0);
+        self.code.extend_from_slice(&[0xFF; 8]);
+        self.code.push(0x80);
+        self.code
+    }
+}
+
+/// Loads an 8-bit value into a register
+fn load(asm: &mut Assembler, x: u8, value: u8) {
+    asm.op(Load { x, value });
+}
+
+/// The logical and arithmetic opcodes, and their flags
+fn alu(asm: &mut Assembler) {
+    load(asm, 0x1, 0x0F);
+    for (x, instruction) in [
+        (0x2, Or { x: 0x2, y: 0x1 }),
+        (0x4, And { x: 0x4, y: 0x1 }),
+        (0x6, Xor { x: 0x6, y: 0x1 }),
+    ] {
+        load(asm, x, 0x3C);
+        load(asm, 0xF, 0xAA);
+        asm.op(instruction).op(Move { x: x + 1, y: 0xF });
+    }
+
+    load(asm, 0x8, 0xF0);
+    load(asm, 0x9, 0x30);
+    asm.op(AddRegisters { x: 0x8, y: 0x9 })
+        .op(Move { x: 0xA, y: 0xF });
+    load(asm, 0xB, 0x10);
+    asm.op(Sub { x: 0xB, y: 0x9 }).op(Move { x: 0xC, y: 0xF });
+    load(asm, 0xD, 0x05);
+    load(asm, 0xE, 0x10);
+    asm.op(SubReversed { x: 0xD, y: 0xE }).dump();
+}
+
+fn check_alu(emulator: &Chip8, quirks: Quirks) {
+    // the flags register as it was before each logical opcode, unless reset
+    let flags = if quirks.contains(Quirks::VF_RESET) {
+        0
+    } else {
+        0xAA
+    };
+    assert_eq!(
+        dumped_registers(emulator),
+        [
+            0x00, 0x0F, 0x3F, flags, 0x0C, flags, 0x33, flags, 0x20, 0x30, 1, 0xE0, 0, 0x0B, 0x10,
+            1
+        ]
+    );
+}
+
+/// The shift opcodes, and arithmetic with the flags register as the destination
+fn shift(asm: &mut Assembler) {
+    load(asm, 0x1, 0x81);
+    load(asm, 0x2, 0x42);
+    asm.op(ShiftRight { x: 0x1, y: 0x2 })
+        .op(Move { x: 0x3, y: 0xF });
+    load(asm, 0x4, 0x81);
+    load(asm, 0x5, 0x42);
+    asm.op(ShiftLeft { x: 0x4, y: 0x5 })
+        .op(Move { x: 0x6, y: 0xF });
+
+    // the flag is written after the result
+    load(asm, 0x7, 0xFF);
+    load(asm, 0xF, 0x02);
+    asm.op(AddRegisters { x: 0xF, y: 0x7 })
+        .op(Move { x: 0x8, y: 0xF });
+    load(asm, 0xF, 0x01);
+    asm.op(Sub { x: 0xF, y: 0x7 }).op(Move { x: 0x9, y: 0xF });
+    load(asm, 0xF, 0x01);
+    asm.op(ShiftLeft { x: 0xF, y: 0x7 }).dump();
+}
+
+fn check_shift(emulator: &Chip8, quirks: Quirks) {
+    let expected = if quirks.contains(Quirks::SHIFTING) {
+        // vX is shifted in place, and `vF <<= 1` leaves the bit shifted out of vF
+        [
+            0, 0x40, 0x42, 1, 0x02, 0x42, 1, 0xFF, 1, 0, 0, 0, 0, 0, 0, 0,
+        ]
+    } else {
+        // vY is shifted into vX, and `vF = v7 << 1` leaves the bit shifted out of v7
+        [
+            0, 0x21, 0x42, 0, 0x84, 0x42, 0, 0xFF, 1, 0, 0, 0, 0, 0, 0, 1,
+        ]
+    };
+    assert_eq!(dumped_registers(emulator), expected);
+}
+
+/// Storing and loading registers, BCD, and adding to the index register
+fn memory(asm: &mut Assembler) {
+    load(asm, 0xA, 234);
+    asm.op(LoadIndex { addr: SCRATCH })
+        .op(StoreDecimal { x: 0xA })
+        .op(LoadRegisters { x: 0x2 })
+        .op(Move { x: 0xB, y: 0x0 })
+        .op(Move { x: 0xC, y: 0x1 })
+        .op(Move { x: 0xD, y: 0x2 });
+
+    // with the memory quirk the second store lands after the first instead of over it
+    for (x, value) in (0..4).zip(1..) {
+        load(asm, x, value);
+    }
+    asm.op(LoadIndex {
+        addr: SCRATCH + 0x10,
+    })
+    .op(StoreRegisters { x: 0x3 });
+    load(asm, 0x0, 0x99);
+    asm.op(StoreRegisters { x: 0x0 });
+
+    load(asm, 0x6, 0x04);
+    asm.op(LoadIndex {
+        addr: SCRATCH + 0x10,
+    })
+    .op(AddIndex { x: 0x6 })
+    .op(LoadRegisters { x: 0x0 })
+    .op(Move { x: 0x7, y: 0x0 })
+    .op(LoadIndex {
+        addr: SCRATCH + 0x10,
+    })
+    .op(LoadRegisters { x: 0x4 })
+    .dump();
+}
+
+fn check_memory(emulator: &Chip8, quirks: Quirks) {
+    let expected = if quirks.contains(Quirks::MEMORY) {
+        [1, 2, 3, 4, 0x99, 0, 4, 0x99, 0, 0, 234, 2, 3, 4, 0, 0]
+    } else {
+        [0x99, 2, 3, 4, 0, 0, 4, 0, 0, 0, 234, 2, 3, 4, 0, 0]
+    };
+    assert_eq!(dumped_registers(emulator), expected);
+    assert_eq!(emulator.memory()[SCRATCH as usize..][..3], [2, 3, 4]);
+}
+
+/// The jump with offset opcode
+fn jump(asm: &mut Assembler) {
+    load(asm, 0x0, 0);
+    load(asm, 0x2, 4);
+    let target = asm.here() + 2;
+    asm.op(JumpOffset { addr: target });
+    // v0 lands here, v2 four bytes later
+    load(asm, 0xA, 1);
+    asm.dump();
+    load(asm, 0xA, 2);
+    asm.dump();
+}
+
+fn check_jump(emulator: &Chip8, quirks: Quirks) {
+    let landed = if quirks.contains(Quirks::JUMPING) {
+        2
+    } else {
+        1
+    };
+    assert_eq!(
+        dumped_registers(emulator),
+        [0, 0, 4, 0, 0, 0, 0, 0, 0, 0, landed, 0, 0, 0, 0, 0]
+    );
+}
+
+/// Sprites drawn off the edges of the display, and collisions with what wraps around
+fn clipping(asm: &mut Assembler) {
+    load(asm, 0x0, 60);
+    load(asm, 0x1, 28);
+    asm.op(LoadIndex { addr: SPRITES }).op(Draw {
+        x: 0,
+        y: 1,
+        rows: 8,
+    });
+
+    // the starting position always wraps
+    load(asm, 0x0, 66);
+    load(asm, 0x1, 40);
+    asm.op(Draw {
+        x: 0,
+        y: 1,
+        rows: 8,
+    })
+    .op(Move { x: 0x4, y: 0xF });
+
+    // only hits anything if the first sprite wrapped
+    load(asm, 0x0, 0);
+    asm.op(LoadIndex { addr: SPRITES + 8 })
+        .op(Draw {
+            x: 0,
+            y: 0,
+            rows: 1,
+        })
+        .op(Move { x: 0x3, y: 0xF })
+        .dump();
+}
+
+fn check_clipping(emulator: &Chip8, quirks: Quirks) {
+    let wrapped = !quirks.contains(Quirks::CLIPPING);
+    let flag = u8::from(wrapped);
+    assert_eq!(
+        dumped_registers(emulator),
+        [0, 40, 0, flag, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, flag]
+    );
+
+    let display = emulator.window();
+    assert!(display.get_pixel(63, 31));
+    // if the block wrapped, its corner and the single pixel cancel out
+    assert_eq!(display.get_pixel(0, 0), !wrapped);
+    assert_eq!(display.get_pixel(3, 3), wrapped);
+    assert!(display.get_pixel(2, 8) && display.get_pixel(9, 15));
+}
+
+/// Draws a row of sprites, more than can be drawn in the frames the test is run for if every
+/// draw waits for the vertical blank
+fn display_wait(asm: &mut Assembler) {
+    load(asm, 0x1, 0);
+    asm.op(LoadIndex { addr: SPRITES + 8 });
+    for i in 0..20 {
+        load(asm, 0x0, i * 3);
+        asm.op(Draw {
+            x: 0,
+            y: 1,
+            rows: 1,
+        });
+    }
+    asm.dump();
+}
+
+fn check_display_wait(emulator: &Chip8, quirks: Quirks) {
+    let display = emulator.window();
+    let drawn = (0..20).filter(|i| display.get_pixel(i * 3, 0)).count();
+    if quirks.contains(Quirks::DISPLAY_WAIT) {
+        // one draw per frame
+        assert_eq!(drawn, 8);
+        assert!(emulator.program_counter() < DUMP);
+    } else {
+        assert_eq!(drawn, 20);
+        assert_eq!(dumped_registers(emulator)[0], 57);
+    }
+}
+
+/// The registers as stored by the dump routine
+fn dumped_registers(emulator: &Chip8) -> [u8; 16] {
+    assert_eq!(
+        emulator.program_counter(),
+        HALT,
+        "program didn't finish the dump"
+    );
+    let mut registers = [0; 16];
+    registers.copy_from_slice(&emulator.memory()[DUMP_BUFFER as usize..][..16]);
+    registers
+}
+
+/// A test program, how many frames to run it for, and what it should have done by then
+struct TestProgram {
+    name: &'static str,
+    frames: u64,
+    build: fn(&mut Assembler),
+    check: fn(&Chip8, Quirks),
+}
+
+const PROGRAMS: [TestProgram; 6] = [
+    TestProgram {
+        name: "alu",
+        frames: 20,
+        build: alu,
+        check: check_alu,
+    },
+    TestProgram {
+        name: "shift",
+        frames: 20,
+        build: shift,
+        check: check_shift,
+    },
+    TestProgram {
+        name: "memory",
+        frames: 20,
+        build: memory,
+        check: check_memory,
+    },
+    TestProgram {
+        name: "jump",
+        frames: 20,
+        build: jump,
+        check: check_jump,
+    },
+    TestProgram {
+        name: "clipping",
+        frames: 20,
+        build: clipping,
+        check: check_clipping,
+    },
+    TestProgram {
+        name: "display-wait",
+        frames: 8,
+        build: display_wait,
+        check: check_display_wait,
+    },
+];
+
+/// Runs a ROM for a number of frames
+fn run(rom: &[u8], quirks: Quirks, frames: u64) -> Chip8 {
+    let mut emulator = Chip8::new().override_quirks(quirks);
+    emulator.load(rom);
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+    emulator
+}
+
+/// Renders the display as text
+fn render(emulator: &Chip8) -> String {
+    let display = emulator.window();
+    let mut text = String::new();
+    for y in 0..display.height() {
+        for x in 0..display.width() {
+            text.push(if display.get_pixel(x, y) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Compares a rendered display against its golden image, or overwrites the golden image if
+/// `UPDATE_GOLDEN` is set
+fn check_golden(name: &str, actual: &str) -> Result<(), String> {
+    let path = golden_dir().join(format!("{name}.txt"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return fs::write(&path, actual).map_err(|e| format!("writing {}: {e}", path.display()));
+    }
+
+    let expected = fs::read_to_string(&path).map_err(|e| {
+        format!(
+            "{name}: reading {}: {e}. Run with UPDATE_GOLDEN=1 to create it.",
+            path.display()
+        )
+    })?;
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!(
+            "{name} differs from {}\nexpected:\n{expected}\nactual:\n{actual}",
+            path.display()
+        ))
+    }
+}
+
+fn assert_all_pass(failures: Vec<String>) {
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn programs_match_golden_images() {
+    let mut failures = Vec::new();
+    for program in &PROGRAMS {
+        let mut asm = Assembler::new();
+        (program.build)(&mut asm);
+        let rom = asm.finish();
+
+        for (profile, quirks) in Quirks::PROFILES {
+            let actual = render(&run(&rom, quirks, program.frames));
+            if let Err(e) = check_golden(&format!("{}.{profile}", program.name), &actual) {
+                failures.push(e);
+            }
+        }
+    }
+    assert_all_pass(failures);
+}
+
+#[test]
+fn programs_compute_known_values() {
+    for program in &PROGRAMS {
+        let mut asm = Assembler::new();
+        (program.build)(&mut asm);
+        let rom = asm.finish();
+
+        for (profile, quirks) in Quirks::PROFILES {
+            println!("{} under {}", program.name, profile);
+            (program.check)(&run(&rom, quirks, program.frames), quirks);
+        }
+    }
+}
+
+/// Runs the test-suite ROMs placed in `tests/roms`, such as Timendus' suite, which isn't
+/// distributed with this repository. Fails if there aren't any.
+#[test]
+#[ignore = "needs test-suite ROMs copied into tests/roms, see tests/roms/README.md"]
+fn roms_match_golden_images() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let entries = fs::read_dir(&dir).unwrap_or_else(|e| panic!("reading {}: {e}", dir.display()));
+
+    let mut failures = Vec::new();
+    let mut roms = 0;
+    for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+        if path.extension().is_none_or(|extension| extension != "ch8") {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let rom = fs::read(&path).unwrap_or_else(|e| panic!("reading {}: {e}", path.display()));
+        roms += 1;
+
+        for (profile, quirks) in Quirks::PROFILES {
+            let actual = render(&run(&rom, quirks, ROM_FRAMES));
+            if let Err(e) = check_golden(&format!("{stem}.{profile}"), &actual) {
+                failures.push(e);
+            }
+        }
+    }
+    assert!(
+        roms > 0,
+        "no .ch8 ROMs in {}, see the README there for where to get them",
+        dir.display()
+    );
+    assert_all_pass(failures);
+}
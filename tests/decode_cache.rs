@@ -0,0 +1,85 @@
+//! Checks that the decode cache never runs an instruction that has since been overwritten
+
+use chip8_rs::emulator::Chip8;
+
+/// Runs `7201`, then overwrites it with `7101` using `Fx55` and runs it again
+const STORE_OVER_ADD: [u8; 12] = [
+    0x60, 0x71, // v0 = 0x71
+    0x61, 0x01, // v1 = 1
+    0x72, 0x01, // v2 += 1, overwritten with v1 += 1
+    0xA2, 0x04, // I = 0x204
+    0xF1, 0x55, // store v0 and v1 at I
+    0x12, 0x04, // jump back to the overwritten instruction
+];
+
+/// Runs a jump to 0x20E, then overwrites its address with 0x202 using `Fx33` and runs it again
+const BCD_OVER_JUMP: [u8; 20] = [
+    0x12, 0x08, // jump to the setup
+    0x72, 0x01, // v2 += 1, only reachable through the overwritten jump
+    0x12, 0x04, // halt
+    0x12, 0x0E, // jump to 0x20E, overwritten with a jump to 0x202
+    0x60, 0xC8, // v0 = 200
+    0xA2, 0x07, // I = 0x207, the jump's address
+    0x12, 0x06, // run the jump
+    0x71, 0x01, // v1 += 1
+    0xF0, 0x33, // store the digits of 200 at I
+    0x12, 0x06, // run the jump again
+];
+
+/// Adds 1 to v0 in a loop
+const COUNT_V0: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+/// Adds 1 to v1 in a loop
+const COUNT_V1: [u8; 4] = [0x71, 0x01, 0x12, 0x00];
+
+fn emulator(rom: &[u8]) -> Chip8 {
+    let mut emulator = Chip8::new().with_decode_cache();
+    emulator.load(rom);
+    emulator
+}
+
+#[test]
+fn stores_over_decoded_instructions_run_the_new_instruction() {
+    let mut emulator = emulator(&STORE_OVER_ADD);
+    for _ in 0..7 {
+        emulator.tick_cpu();
+    }
+    assert_eq!(emulator.memory()[0x204..0x206], [0x71, 0x01]);
+    assert_eq!(emulator.registers()[2], 1);
+    assert_eq!(emulator.registers()[1], 2);
+}
+
+#[test]
+fn bcd_over_decoded_instructions_runs_the_new_instruction() {
+    let mut emulator = emulator(&BCD_OVER_JUMP);
+    for _ in 0..20 {
+        emulator.tick_cpu();
+    }
+    assert_eq!(emulator.memory()[0x206..0x208], [0x12, 0x02]);
+    assert_eq!(emulator.registers()[1], 1);
+    assert_eq!(emulator.registers()[2], 1);
+    assert_eq!(emulator.program_counter(), 0x204);
+}
+
+#[test]
+fn loading_discards_decoded_instructions() {
+    let mut emulator = emulator(&COUNT_V0);
+    emulator.run_frame();
+    emulator.load(&COUNT_V1);
+    let counted = emulator.registers()[0];
+    emulator.run_frame();
+    assert_eq!(emulator.registers()[0], counted);
+    assert!(emulator.registers()[1] > 0);
+}
+
+#[test]
+fn resetting_discards_decoded_instructions() {
+    let mut emulator = emulator(&COUNT_V0);
+    emulator.run_frame();
+    emulator.reset();
+
+    // memory at 0x200 is empty now, so the cached `7001` must not run
+    emulator.tick_cpu();
+    assert_eq!(emulator.registers()[0], 0);
+    assert_eq!(emulator.program_counter(), 0x202);
+}
@@ -0,0 +1,205 @@
+//! Tests for the reinforcement learning environment and its reward expressions
+
+use chip8_rs::{emulator::Chip8, environment::Environment, expression::Expression};
+
+/// Sets the delay timer to 200 and halts, so that the timer counts frames
+const COUNTDOWN: [u8; 6] = [0x60, 200, 0xF0, 0x15, 0x12, 0x04];
+
+/// Adds 1 to v0 in a loop while key 5 is held
+const HOLD_TO_COUNT: [u8; 8] = [0x61, 0x05, 0xE1, 0xA1, 0x70, 0x01, 0x12, 0x02];
+
+/// Adds random numbers to v1 in a loop
+const ADD_RANDOM: [u8; 6] = [0xC0, 0xFF, 0x81, 0x04, 0x12, 0x00];
+
+fn evaluate(source: &str) -> i64 {
+    let emulator = Chip8::new();
+    match source.parse::<Expression>() {
+        Ok(expression) => expression.evaluate(&emulator, &expression.capture(&emulator)),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn expressions_follow_precedence() {
+    assert_eq!(evaluate("1 + 2 * 3"), 7);
+    assert_eq!(evaluate("(1 + 2) * 3"), 9);
+    assert_eq!(evaluate("0x10 >> 2"), 4);
+    assert_eq!(evaluate("3 & 1 | 4"), 5);
+    assert_eq!(evaluate("1 < 2 && 2 <= 2 || 0"), 1);
+    assert_eq!(evaluate("!0 + -1"), 0);
+    assert_eq!(evaluate("7 - 2 - 1"), 4);
+}
+
+#[test]
+fn expressions_read_the_machine() {
+    // the font starts at address 0
+    assert_eq!(evaluate("mem[0]"), 0xF0);
+    assert_eq!(evaluate("mem[4096 + 1]"), 0x90);
+    assert_eq!(evaluate("pc"), 0x200);
+    assert_eq!(evaluate("vF + i + dt + st"), 0);
+}
+
+#[test]
+fn expressions_never_panic() {
+    assert_eq!(evaluate("5 / 0"), 0);
+    assert_eq!(evaluate("5 % 0"), 0);
+    assert_eq!(evaluate("1 << 100"), 0);
+    assert_eq!(evaluate("mem[-1]"), 0);
+}
+
+#[test]
+fn invalid_expressions_are_rejected() {
+    for source in [
+        "",
+        "v10",
+        "1 +",
+        "mem[1",
+        "foo",
+        "prev(prev(v0))",
+        "1 = 1",
+        "(1",
+    ] {
+        assert!(
+            source.parse::<Expression>().is_err(),
+            "`{}` should be invalid",
+            source
+        );
+    }
+
+    // too deeply nested or too long to parse and evaluate without overflowing the stack
+    let long = [
+        "(".repeat(100_000),
+        "-".repeat(100_000) + "1",
+        "mem[".repeat(100_000),
+        "1 + ".repeat(100_000) + "1",
+    ];
+    for source in long {
+        assert!(source.parse::<Expression>().is_err());
+    }
+    assert_eq!(evaluate(&("(".repeat(64) + "1" + &")".repeat(64))), 1);
+    assert_eq!(evaluate(&("-".repeat(64) + "1")), 1);
+    let deep = [
+        "(".repeat(65) + "1" + &")".repeat(65),
+        "-".repeat(65) + "1",
+        "!".repeat(65) + "0",
+    ];
+    for source in deep {
+        assert!(matches!(source.parse::<Expression>(), Err(e) if e.contains("Nested")));
+    }
+}
+
+#[test]
+fn prev_uses_captured_values() {
+    let mut emulator = Chip8::new();
+    emulator.load(&COUNTDOWN);
+    let Ok(expression) = "prev(dt) - dt".parse::<Expression>() else {
+        panic!("valid expression");
+    };
+
+    emulator.run_frame();
+    let captured = expression.capture(&emulator);
+    for _ in 0..3 {
+        emulator.run_frame();
+    }
+    assert_eq!(expression.evaluate(&emulator, &captured), 3);
+}
+
+fn countdown_env() -> Environment {
+    let (Ok(reward), Ok(done)) = ("prev(dt) - dt".parse(), "dt <= 190".parse()) else {
+        panic!("valid expressions");
+    };
+    Environment::new(COUNTDOWN.to_vec())
+        .with_reward(reward)
+        .with_done(done)
+}
+
+#[test]
+fn actions_are_held_for_the_frame_skip() {
+    let mut env = countdown_env().with_frame_skip(4);
+    env.step(0);
+
+    let (_, reward, done) = env.step(0);
+    assert_eq!(reward, 4.0);
+    assert!(!done);
+}
+
+#[test]
+fn steps_end_early_when_done() {
+    let mut env = countdown_env().with_frame_skip(4);
+    env.step(0);
+
+    // the timer is set to 200 and has ticked 4 times, then 4 more
+    assert!(!env.step(0).2);
+    let (_, reward, done) = env.step(0);
+    assert!(done);
+    assert_eq!(env.emulator().delay_timer(), 190);
+    assert_eq!(reward, 2.0);
+
+    env.reset();
+    assert_eq!(env.emulator().delay_timer(), 0);
+}
+
+#[test]
+fn keys_are_held_from_the_action() {
+    let mut env = Environment::new(HOLD_TO_COUNT.to_vec()).with_frame_skip(10);
+    env.step(0);
+    assert_eq!(env.emulator().registers()[0], 0);
+
+    env.step(1 << 5);
+    assert!(env.emulator().registers()[0] > 0);
+}
+
+fn sticky_env(probability: f64) -> Environment {
+    match Environment::new(HOLD_TO_COUNT.to_vec()).with_sticky_actions(probability) {
+        Ok(env) => env,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn sticky_actions_are_reproducible() {
+    let run = |seed| {
+        let mut env = sticky_env(0.5).with_seed(seed);
+        (0..50)
+            .map(|step| {
+                env.step(if step % 2 == 0 { 1 << 5 } else { 0 });
+                env.emulator().registers()[0]
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(run(1), run(1));
+
+    // always sticking means the keys never change from the start of the episode
+    let mut env = sticky_env(1.0);
+    for _ in 0..10 {
+        env.step(1 << 5);
+    }
+    assert_eq!(env.emulator().registers()[0], 0);
+}
+
+#[test]
+fn invalid_sticky_actions_are_rejected() {
+    for probability in [f64::NAN, f64::INFINITY, -0.1, 1.5] {
+        let env = Environment::new(HOLD_TO_COUNT.to_vec()).with_sticky_actions(probability);
+        assert!(env.is_err(), "{} should be invalid", probability);
+    }
+}
+
+#[test]
+fn random_numbers_are_reproducible() {
+    // v1 at the end of each of a few episodes
+    let run = |seed| {
+        let mut env = Environment::new(ADD_RANDOM.to_vec()).with_seed(seed);
+        (0..3)
+            .map(|_| {
+                env.reset();
+                env.step(0);
+                env.emulator().registers()[1]
+            })
+            .collect::<Vec<_>>()
+    };
+    let episodes = run(1);
+    assert_eq!(episodes, run(1));
+    assert_ne!(episodes, run(2));
+    assert_ne!(episodes[0], episodes[1]);
+}
@@ -0,0 +1,92 @@
+//! Checks that skipping the rest of a frame once the program is idling doesn't change what the
+//! program does, by comparing against running every cycle of every frame.
+
+use chip8_rs::emulator::{Chip8, Quirks, SaveState, cycles_in_frame};
+
+/// Counts to 30 in v1, then halts by jumping to itself
+const COUNT_THEN_HALT: [u8; 8] = [0x71, 0x01, 0x31, 0x1E, 0x12, 0x00, 0x12, 0x06];
+
+/// Waits for the delay timer to reach 0 with `Fx07; 3xkk; 1nnn`, counting each wait in v2
+const WAIT_FOR_ZERO: [u8; 14] = [
+    0x60, 0x07, // v0 = 7
+    0xF0, 0x15, // delay timer = v0
+    0xF1, 0x07, // v1 = delay timer
+    0x31, 0x00, // skip if v1 == 0
+    0x12, 0x04, // jump back to the load
+    0x72, 0x01, // v2 += 1
+    0x12, 0x00, // start again
+];
+
+/// Waits while the delay timer is 5 with `Fx07; 4xkk; 1nnn`, after waiting for it to get there
+const WAIT_WHILE_FIVE: [u8; 18] = [
+    0x60, 0x09, // v0 = 9
+    0xF0, 0x15, // delay timer = v0
+    0xF1, 0x07, // v1 = delay timer
+    0x41, 0x05, // skip if v1 != 5
+    0x12, 0x04, // jump back to the load
+    0x31, 0x00, // skip if v1 == 0
+    0x12, 0x04, // keep waiting
+    0x72, 0x01, // v2 += 1
+    0x12, 0x00, // start again
+];
+
+/// Draws a sprite every frame, which waits for the VBlank under the display wait quirk
+const DRAW_EVERY_FRAME: [u8; 6] = [0xD0, 0x15, 0x70, 0x01, 0x12, 0x00];
+
+/// The state of the machine, without the frame counter since running cycles by hand doesn't
+/// advance it
+fn state(emulator: &Chip8) -> SaveState {
+    SaveState {
+        frame: 0,
+        ..emulator.save_state()
+    }
+}
+
+/// Runs a program frame by frame with [`Chip8::run_frame`], which skips idle cycles, and with
+/// every cycle run by hand, checking they stay in the same state
+fn check_skipping(rom: &[u8]) {
+    for quirks in [Quirks::CHIP_8, Quirks::SUPER_CHIP] {
+        for decode_cache in [false, true] {
+            let mut skipping = Chip8::new().override_quirks(quirks);
+            let mut exact = Chip8::new().override_quirks(quirks);
+            if decode_cache {
+                skipping = skipping.with_decode_cache();
+                exact = exact.with_decode_cache();
+            }
+            skipping.load(rom);
+            exact.load(rom);
+
+            for frame in 0..300 {
+                skipping.run_frame();
+                for _ in 0..cycles_in_frame(frame) {
+                    exact.tick_cpu();
+                }
+                exact.tick_timers();
+
+                assert_eq!(
+                    state(&skipping),
+                    state(&exact),
+                    "frame {} with {:?}",
+                    frame,
+                    quirks
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn halting_loops() {
+    check_skipping(&COUNT_THEN_HALT);
+}
+
+#[test]
+fn delay_timer_loops() {
+    check_skipping(&WAIT_FOR_ZERO);
+    check_skipping(&WAIT_WHILE_FIVE);
+}
+
+#[test]
+fn display_wait() {
+    check_skipping(&DRAW_EVERY_FRAME);
+}
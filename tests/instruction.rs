@@ -0,0 +1,17 @@
+//! Tests for decoding and encoding instructions
+
+use chip8_rs::instruction::{Instruction, Platform};
+
+#[test]
+fn every_opcode_encodes_back_to_itself() {
+    for opcode in 0..=u16::MAX {
+        let instruction = Instruction::decode(opcode, Platform::Chip8);
+        assert_eq!(
+            instruction.encode(),
+            opcode,
+            "{:04x} decoded as {:?}",
+            opcode,
+            instruction
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Tests for forking and comparing machine state
+
+use std::{
+    collections::HashSet,
+    hash::{BuildHasher, RandomState},
+};
+
+use chip8_rs::{
+    emulator::{Chip8, FRAME_PERIOD},
+    machine::Machine,
+};
+
+/// Calls a subroutine that returns straight away, then halts
+const CALL_AND_HALT: [u8; 6] = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+
+/// Adds 1 to v0 in a loop
+const COUNT: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+/// Adds random numbers to v1 in a loop
+const ADD_RANDOM: [u8; 6] = [0xC0, 0xFF, 0x81, 0x04, 0x12, 0x00];
+
+fn emulator(rom: &[u8]) -> Chip8 {
+    let mut emulator = Chip8::new();
+    emulator.load(rom);
+    emulator
+}
+
+#[test]
+fn forked_machines_replay_identically() {
+    let mut emulator = emulator(&COUNT);
+    emulator.run_frame();
+    let fork = emulator.machine().clone();
+
+    for _ in 0..10 {
+        emulator.run_frame();
+    }
+    let first = emulator.machine().clone();
+    assert_ne!(first, fork);
+
+    emulator.set_machine(fork);
+    for _ in 0..10 {
+        emulator.run_frame();
+    }
+    assert_eq!(emulator.machine(), &first);
+}
+
+#[test]
+fn forked_machines_generate_the_same_random_numbers() {
+    let mut emulator = emulator(&ADD_RANDOM);
+    emulator.run_frame();
+    let fork = emulator.machine().clone();
+
+    emulator.run_frame();
+    let first = emulator.machine().clone();
+    emulator.set_machine(fork.clone());
+    emulator.run_frame();
+    assert_eq!(emulator.machine(), &first);
+
+    // a differently seeded generator makes the program do something else
+    let mut reseeded = Chip8::new().with_seed(1);
+    reseeded.load(&ADD_RANDOM);
+    reseeded.run_frame();
+    reseeded.run_frame();
+    assert_ne!(reseeded.registers(), first.registers());
+}
+
+#[test]
+fn machines_only_differ_by_frame_when_frames_run_differently() {
+    let mut emulator = emulator(&CALL_AND_HALT);
+    emulator.run_frame();
+    let halted = emulator.machine().clone();
+
+    emulator.run_frame();
+    assert_ne!(emulator.machine(), &halted);
+
+    for _ in 1..FRAME_PERIOD {
+        emulator.run_frame();
+    }
+    assert_eq!(emulator.machine(), &halted);
+    assert_ne!(emulator.machine().frame(), halted.frame());
+
+    let hasher = RandomState::new();
+    assert_eq!(
+        hasher.hash_one(emulator.machine()),
+        hasher.hash_one(&halted)
+    );
+}
+
+#[test]
+fn machines_ignore_popped_stack_entries() {
+    let mut emulator = emulator(&CALL_AND_HALT);
+    emulator.run_frame();
+    assert!(emulator.machine().stack().is_empty());
+
+    // the restored machine's stack never had the return address pushed
+    let mut restored = Chip8::new();
+    if let Err(e) = restored.load_state(&emulator.save_state()) {
+        panic!("{}", e);
+    }
+    assert_eq!(restored.machine(), emulator.machine());
+
+    let hasher = RandomState::new();
+    assert_eq!(
+        hasher.hash_one(restored.machine()),
+        hasher.hash_one(emulator.machine())
+    );
+    let explored = HashSet::from([restored.machine().clone(), Machine::new()]);
+    assert!(explored.contains(emulator.machine()));
+}
+
+#[test]
+fn setting_the_machine_discards_decoded_instructions() {
+    let mut emulator = Chip8::new().with_decode_cache();
+    emulator.load(&COUNT);
+    let counting = emulator.machine().clone();
+    emulator.run_frame();
+    assert!(emulator.registers()[0] > 0);
+
+    // the cached `7001` at 0x200 must not run once the memory holds a different program
+    let mut halting = Chip8::new();
+    halting.load(&CALL_AND_HALT);
+    emulator.set_machine(halting.machine().clone());
+    emulator.run_frame();
+    assert_eq!(emulator.registers()[0], 0);
+    assert_eq!(emulator.program_counter(), 0x202);
+
+    emulator.set_machine(counting);
+    emulator.run_frame();
+    assert!(emulator.registers()[0] > 0);
+}
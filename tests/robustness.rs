@@ -0,0 +1,59 @@
+//! Programs that used to panic the interpreter, each run under every combination of quirks.
+//! The fuzz targets in `fuzz/` search for more of these.
+
+use chip8_rs::emulator::{Chip8, Quirks};
+
+/// Runs a program for a few seconds under every combination of quirks, with and without the
+/// decode cache
+fn run_everywhere(rom: &[u8]) {
+    for bits in 0..=Quirks::all().bits() {
+        for decode_cache in [false, true] {
+            let mut emulator = Chip8::new().override_quirks(Quirks::from_bits_truncate(bits));
+            if decode_cache {
+                emulator = emulator.with_decode_cache();
+            }
+            emulator.load(rom);
+            for _ in 0..180 {
+                emulator.run_frame();
+            }
+        }
+    }
+}
+
+#[test]
+fn return_with_empty_stack() {
+    run_everywhere(&[0x00, 0xEE]);
+}
+
+#[test]
+fn stack_overflow() {
+    // calls itself forever
+    run_everywhere(&[0x22, 0x00]);
+}
+
+#[test]
+fn memory_access_past_the_end() {
+    // I = 0xFFF, then draw, BCD, store, and load
+    run_everywhere(&[
+        0xAF, 0xFF, 0xD0, 0x1F, 0xF0, 0x33, 0xFF, 0x55, 0xFF, 0x65, 0x12, 0x00,
+    ]);
+}
+
+#[test]
+fn index_register_overflow() {
+    // v0 = 0xFF, then keep adding it to I
+    run_everywhere(&[0x60, 0xFF, 0xF0, 0x1E, 0x12, 0x02]);
+}
+
+#[test]
+fn key_index_out_of_range() {
+    // v0 = 0xFF, then skip on key v0 pressed and not pressed
+    run_everywhere(&[0x60, 0xFF, 0xE0, 0x9E, 0xE0, 0xA1, 0x12, 0x02]);
+}
+
+#[test]
+fn program_counter_past_the_end() {
+    // jump to the last byte of memory and run through the end, or jump past it with v0 or vF
+    run_everywhere(&[0x1F, 0xFF]);
+    run_everywhere(&[0x60, 0xFF, 0x6F, 0xFF, 0xBF, 0xFF]);
+}